@@ -2,5 +2,15 @@
 
 set -exu
 
+install_directory=/root/xdxd-snapshot-rotator
+units_directory=/etc/systemd/system
 
-sudo rsync -aP ./target/x86_64-unknown-linux-musl/release/xdxd-snapshot-rotator /root/xdxd-snapshot-rotator/xdxd-snapshot-rotator
+sudo install -D -m 755 ./target/x86_64-unknown-linux-musl/release/xdxd-snapshot-rotator $install_directory/xdxd-snapshot-rotator
+
+sudo CONFIG_DIRECTORY=$install_directory $install_directory/xdxd-snapshot-rotator install-units --output-directory $units_directory
+
+sudo systemctl daemon-reload
+
+for timer in $(cd $units_directory && ls xdxd-snapshot-rotator-*.timer); do
+  sudo systemctl enable --now "$timer"
+done
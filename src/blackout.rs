@@ -0,0 +1,91 @@
+use chrono::{DateTime, Local, Datelike};
+
+use crate::global::prelude::*;
+use crate::global::app_config::BlackoutWindow;
+
+/// A window matches when every constraint it specifies matches.
+/// Time ranges may wrap around midnight (`22:00` - `02:00`); weekdays and dates are checked against `now`.
+fn is_active(window: &BlackoutWindow, now: &DateTime<Local>) -> Result<bool> {
+
+    if let Some(dates) = window.parsed_dates()? {
+        if !dates.contains(&now.date().naive_local()) {
+            return Ok(false);
+        }
+    }
+
+    if let Some(weekdays) = window.parsed_weekdays()? {
+        if !weekdays.contains(&now.weekday().num_days_from_monday()) {
+            return Ok(false);
+        }
+    }
+
+    match window.time_range()? {
+        Some((start, end)) => {
+
+            let time = now.time();
+
+            if start <= end {
+                Ok(start <= time && time < end)
+            } else {
+                Ok(time >= start || time < end)
+            }
+        },
+        None => Ok(true),
+    }
+}
+
+pub fn describe(window: &BlackoutWindow) -> String {
+
+    if let Some(name) = &window.name {
+        return name.clone();
+    }
+
+    let mut parts = Vec::new();
+
+    if let Some(dates) = &window.dates {
+        parts.push(dates.join(","));
+    }
+
+    if let Some(weekdays) = &window.weekdays {
+        parts.push(weekdays.join(","));
+    }
+
+    if let (Some(start_time), Some(end_time)) = (&window.start_time, &window.end_time) {
+        parts.push(format!("{}-{}", start_time, end_time));
+    }
+
+    parts.join(" ")
+}
+
+/// Returns the first blackout window (vm specific or global) that is active at `now`.
+pub fn active_blackout(config: &VmConfig, now: &DateTime<Local>) -> Result<Option<BlackoutWindow>> {
+
+    let windows = config.blackout.iter()
+        .chain(app_config().blackout.iter())
+        .flat_map(|x| x.iter())
+        .collect_vec();
+
+    for window in windows {
+        if is_active(window, now)? {
+            return Ok(Some(window.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the reason for skipping the vm if a blackout window is active and the run is not forced.
+pub fn blackout_reason(config: &VmConfig, force: bool) -> Result<Option<String>> {
+
+    let window = match active_blackout(config, &Local::now())? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    if force {
+        log!("Blackout window `{}` is active for vm `{}`, but the run is forced.", describe(&window), config.vm_name);
+        return Ok(None);
+    }
+
+    Ok(Some(format!("Blackout window `{}` is active for vm `{}`.", describe(&window), config.vm_name)))
+}
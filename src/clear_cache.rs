@@ -1,14 +1,17 @@
 use clap::Arg;
 
 use crate::global::prelude::*;
-use crate::snapshot_helper::clear_cache;
+use crate::snapshot_helper::{clear_cache, lock_vm};
+use crate::blackout::blackout_reason;
 
 struct ClearCacheCommandOptions {
     vm_name: String,
+    force: bool,
 }
 
 fn clear_cache_command_options() -> Result<ClearCacheCommandOptions> {
     const VM_NAME_VALUE: &str = "vm-name";
+    const FORCE_VALUE: &str = "force";
 
     let matches = cli().command_config(|x| {
         x.arg(Arg::with_name(VM_NAME_VALUE)
@@ -18,6 +21,10 @@ fn clear_cache_command_options() -> Result<ClearCacheCommandOptions> {
             .help("The name of virtual machine.")
             .required(true)
             .takes_value(true)
+        ).arg(Arg::with_name(FORCE_VALUE)
+            .short("f")
+            .long(FORCE_VALUE)
+            .help("Ignore blackout windows.")
         )
     });
 
@@ -25,7 +32,8 @@ fn clear_cache_command_options() -> Result<ClearCacheCommandOptions> {
         .or_error(&format!("No value for: {}", VM_NAME_VALUE))?;
 
     Ok(ClearCacheCommandOptions {
-        vm_name: vm_name.to_string()
+        vm_name: vm_name.to_string(),
+        force: matches.is_present(FORCE_VALUE),
     })
 }
 
@@ -37,6 +45,13 @@ pub fn clear_cache_command() -> Result {
         .and_then(|x| x.get(&options.vm_name).cloned())
         .or_error(&format!("``xdxd-snapshot-rotator` not configured for vm `{}`", options.vm_name))?;
 
+    if let Some(reason) = blackout_reason(&config, options.force)? {
+        log!("{} Skipping.", reason);
+        return Ok(());
+    }
+
+    let _lock = lock_vm(&config)?;
+
     clear_cache(&config)?;
 
     Ok(())
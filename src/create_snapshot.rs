@@ -1,15 +1,34 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
 use clap::Arg;
+use chrono::{DateTime, Utc};
 
 use crate::global::prelude::*;
-use crate::snapshot_helper::clear_cache;
+use crate::global::error_handler::report_error;
+use crate::global::notifier::NotificationEvent;
+use crate::blackout::blackout_reason;
+use crate::hooks::run_hook;
+use crate::guest_agent::{run_guest_hooks, run_guest_command};
+use crate::heartbeat::{ping_heartbeat, HeartbeatSignal};
+use crate::run_history::{record_run, RunRecord};
+use crate::metrics::update_metrics;
+use crate::snapshot_helper::{clear_cache, verify_snapshot, lock_vm, acquire_snapshot_slot, pin_snapshot, RemainingSnapshot};
+use crate::snapshot_report::{build_snapshot_report, SnapshotReport};
 
 struct CreateCommandOptions {
-    vm_name: String,
+    vm_names: Vec<String>,
+    all: bool,
+    concurrency: usize,
+    force: bool,
 }
 
 fn create_command_options() -> Result<CreateCommandOptions> {
 
     const VM_NAME_VALUE: &str = "vm-name";
+    const ALL_VALUE: &str = "all";
+    const CONCURRENCY_VALUE: &str = "concurrency";
+    const FORCE_VALUE: &str = "force";
 
     let matches = cli().command_config(|x| {
 
@@ -17,43 +36,347 @@ fn create_command_options() -> Result<CreateCommandOptions> {
             .short("n")
             .long(VM_NAME_VALUE)
             .value_name(VM_NAME_VALUE)
-            .help("The name of virtual machine.")
-            .required(true)
+            .help("The name of virtual machine. Can be repeated.")
+            .required_unless(ALL_VALUE)
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true)
+        ).arg(Arg::with_name(ALL_VALUE)
+            .short("a")
+            .long(ALL_VALUE)
+            .help("Create snapshots for all configured virtual machines.")
+            .conflicts_with(VM_NAME_VALUE)
+        ).arg(Arg::with_name(CONCURRENCY_VALUE)
+            .short("c")
+            .long(CONCURRENCY_VALUE)
+            .value_name(CONCURRENCY_VALUE)
+            .help("How many virtual machines to process at the same time.")
+            .default_value("1")
             .takes_value(true)
+        ).arg(Arg::with_name(FORCE_VALUE)
+            .short("f")
+            .long(FORCE_VALUE)
+            .help("Ignore blackout windows.")
         )
     });
 
-    let vm_name = matches.value_of(VM_NAME_VALUE)
-        .or_error(&format!("No value for: {}", VM_NAME_VALUE))?;
+    let vm_names = matches.values_of(VM_NAME_VALUE)
+        .map(|x| x.map(|y| y.to_string()).collect_vec())
+        .unwrap_or_default();
+
+    let concurrency = matches.value_of(CONCURRENCY_VALUE)
+        .or_error(&format!("No value for: {}", CONCURRENCY_VALUE))?
+        .parse::<usize>()
+        .replace_error(|| CustomError::user_error(&format!("Invalid value for: {}", CONCURRENCY_VALUE)))?;
+
+    if concurrency == 0 {
+        return Err(CustomError::user_error(&format!("The value for `{}` must be greater than 0.", CONCURRENCY_VALUE)));
+    }
 
     Ok(CreateCommandOptions {
-        vm_name: vm_name.to_string()
+        vm_names,
+        all: matches.is_present(ALL_VALUE),
+        concurrency,
+        force: matches.is_present(FORCE_VALUE),
     })
 }
 
+fn selected_vm_configs(options: &CreateCommandOptions) -> Result<Vec<VmConfig>> {
+
+    let snapshot_config = app_config().snapshot_config.clone().unwrap_or_default();
+
+    if options.all {
+        return Ok(snapshot_config.values().cloned().order_by(|x| x.vm_name.clone()).collect_vec());
+    }
+
+    let mut configs = Vec::new();
+
+    for vm_name in &options.vm_names {
+
+        let config = snapshot_config.get(vm_name).cloned()
+            .or_error(&format!("``xdxd-snapshot-rotator` not configured for vm `{}`", vm_name))?;
+
+        configs.push(config);
+    }
+
+    Ok(configs)
+}
+
 pub fn create_shapshot_command() -> Result {
 
     let options = create_command_options()?;
 
-    let config = app_config().snapshot_config.as_ref()
-        .and_then(|x| x.get(&options.vm_name).cloned())
-        .or_error(&format!("``xdxd-snapshot-rotator` not configured for vm `{}`", options.vm_name))?;
+    email_outbox::retry_outbox()?;
 
-    let now = app_start_time();
+    let configs = selected_vm_configs(&options)?;
+
+    if !options.all && configs.len() == 1 {
+
+        let config = &configs[0];
+
+        if let Some(reason) = blackout_reason(config, options.force)? {
+            log!("{} Skipping.", reason);
+            ping_heartbeat(config, HeartbeatSignal::Skipped(reason))?;
+            return Ok(());
+        }
+
+        match create_snapshot(config, &CreateSnapshotOptions::default()) {
+            Ok(result) => notifier::notify(&NotificationEvent::Success { vm: config, result: &result })?,
+            Err(err) => {
+                let report_result = report_error(&err);
+                notifier::notify(&NotificationEvent::Error { error: &err, vm: Some(config) })?;
+                report_result?;
+
+                return Err(CustomError::user_error(&format!("Snapshot creation failed for vm `{}`.", config.vm_name)));
+            }
+        }
+
+        return Ok(());
+    }
+
+    let results = run_batch(&configs, options.concurrency, options.force)?;
+
+    notifier::notify(&NotificationEvent::Summary { results: &results })?;
+
+    let failed = results.iter()
+        .filter(|x| x.error.is_some())
+        .map(|x| x.vm_name.clone())
+        .collect_vec();
+
+    if !failed.is_empty() {
+        return Err(CustomError::user_error(&format!(
+            "Snapshot creation failed for {} of {} vm(s): {}",
+            failed.len(),
+            results.len(),
+            failed.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// The outcome of creating a snapshot for a single vm as part of a batch.
+#[derive(Debug)]
+pub struct VmRunResult {
+    pub vm_name: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub slot_wait_seconds: Option<u64>,
+    pub skipped: Option<String>,
+    pub error: Option<String>,
+    pub report: Option<SnapshotReport>,
+}
+
+/// Creates snapshots for all given vms using up to `concurrency` worker threads.
+/// A failure for one vm is logged and recorded in its result, the other vms are still processed.
+fn run_batch(configs: &[VmConfig], concurrency: usize, force: bool) -> Result<Vec<VmRunResult>> {
+
+    let next_index = AtomicUsize::new(0);
+
+    let worker_results = ::std::thread::scope(|scope| {
+
+        let workers = (0..concurrency.min(configs.len()))
+            .map(|_| scope.spawn(|| {
+
+                let mut results = Vec::new();
+
+                while let Some(config) = configs.get(next_index.fetch_add(1, Ordering::SeqCst)) {
+                    results.push(run_vm(config, force));
+                }
+
+                results
+            }))
+            .collect_vec();
+
+        workers.into_iter()
+            .map(|x| x.join())
+            .collect_vec()
+    });
+
+    let mut results = Vec::new();
+
+    for worker_result in worker_results {
+        results.extend(worker_result.on_error("A batch worker thread failed for some reason.")?);
+    }
+
+    Ok(results.into_iter().order_by(|x| x.vm_name.clone()).collect_vec())
+}
+
+/// Always returns a result for the vm, so that a failure never drops it from the summary.
+fn run_vm(config: &VmConfig, force: bool) -> VmRunResult {
+
+    let started_at = Utc::now();
+
+    let mut result = VmRunResult {
+        vm_name: config.vm_name.clone(),
+        started_at,
+        finished_at: started_at,
+        slot_wait_seconds: None,
+        skipped: None,
+        error: None,
+        report: None,
+    };
+
+    if let Err(err) = run_vm_snapshot(config, force, &mut result) {
+
+        // The error is in the summary either way, failing to log it should not stop the worker.
+        let _ = report_error(&err);
+
+        result.error = Some(err.kind.to_string());
+    }
+
+    result.finished_at = Utc::now();
+
+    result
+}
+
+fn run_vm_snapshot(config: &VmConfig, force: bool, result: &mut VmRunResult) -> Result {
+
+    if let Some(reason) = blackout_reason(config, force)? {
+
+        log!("{} Skipping.", reason);
+
+        ping_heartbeat(config, HeartbeatSignal::Skipped(reason.clone()))?;
+
+        result.skipped = Some(reason);
+
+        return Ok(());
+    }
+
+    log!("Creating snapshot for vm `{}` ...", config.vm_name);
+
+    let snapshot = create_snapshot(config, &CreateSnapshotOptions::default())?;
+
+    result.slot_wait_seconds = Some(snapshot.slot_wait.as_secs());
+    result.report = Some(build_snapshot_report(config, &snapshot));
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct CreateSnapshotOptions {
+    /// Added to the snapshot name, after the vm name.
+    pub label: Option<String>,
+    /// Excludes the new snapshot from rotation until the given time.
+    pub pinned_until: Option<DateTime<Utc>>,
+}
+
+pub struct CreateSnapshotResult {
+    pub snapshot_name: String,
+    pub slot_wait: Duration,
+    pub deleted_snapshots: Vec<String>,
+    pub remaining_snapshots: Vec<RemainingSnapshot>,
+    /// From taking the vm lock to the end of the rotation.
+    pub duration: Duration,
+}
+
+/// Creates a snapshot for the vm, verifies it and rotates the old ones.
+/// The heartbeat of the vm is pinged when the run starts and when it ends and the run is recorded in the run history and the metrics.
+pub fn create_snapshot(config: &VmConfig, options: &CreateSnapshotOptions) -> Result<CreateSnapshotResult> {
+
+    let started_at = Utc::now();
+
+    ping_heartbeat(config, HeartbeatSignal::Start)?;
+
+    let result = create_and_rotate(config, options);
+
+    match &result {
+        Ok(_) => ping_heartbeat(config, HeartbeatSignal::Success)?,
+        Err(err) => ping_heartbeat(config, HeartbeatSignal::Failure(err.kind.to_string()))?,
+    }
+
+    let record = RunRecord {
+        vm_name: config.vm_name.clone(),
+        started_at,
+        finished_at: Utc::now(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|x| x.kind.to_string()),
+        snapshot_name: result.as_ref().ok().map(|x| x.snapshot_name.clone()),
+        deleted_snapshots: result.as_ref().map(|x| x.deleted_snapshots.clone()).unwrap_or_default(),
+    };
+
+    update_metrics(&record, result.as_ref().ok().map(|x| &x.remaining_snapshots[..]))?;
+
+    record_run(record)?;
+
+    result
+}
+
+fn create_and_rotate(config: &VmConfig, options: &CreateSnapshotOptions) -> Result<CreateSnapshotResult> {
+
+    let started_at = Instant::now();
+
+    let _lock = lock_vm(config)?;
+
+    let now = Utc::now();
+
+    let name_prefix = match &options.label {
+        Some(label) => format!("{}.{}", config.vm_name, label),
+        None => config.vm_name.clone(),
+    };
 
     let snapshot_name = format!(
         "{}.{}.{}",
-        config.vm_name,
+        name_prefix,
         now.format("%Y-%m-%d_%H-%M-%S").to_string(),
         now.timestamp().to_string()
     );
 
-    bash_exec!("virsh snapshot-create-as {} --name {}", config.vm_name, snapshot_name);
+    let slot = acquire_snapshot_slot(config)?;
 
-    clear_cache(&config)?;
+    // Every `pre` step that succeeded gets its `post` step, even if a later step fails.
+    run_hook(config, "pre_snapshot", &config.pre_snapshot, &snapshot_name)?;
 
-    email_report::send_success_report(&config)?;
+    // Once one `pre_snapshot_guest` command succeeded, the guest may be frozen or locked,
+    // so all `post_snapshot_guest` commands run even if a later `pre` command fails.
+    let pre_snapshot_guest = config.pre_snapshot_guest.clone().unwrap_or_default();
 
-    Ok(())
-}
+    let mut run_post_snapshot_guest = pre_snapshot_guest.is_empty();
+
+    do_try::run(|| {
+
+        do_try::run(|| {
 
+            for command in &pre_snapshot_guest {
+                run_guest_command(config, "pre_snapshot_guest", command)?;
+                run_post_snapshot_guest = true;
+            }
+
+            bash_exec!("virsh snapshot-create-as {} --name {}", config.vm_name, snapshot_name);
+            Ok(())
+        }).finally(|| {
+
+            if !run_post_snapshot_guest {
+                return Ok(());
+            }
+
+            run_guest_hooks(config, "post_snapshot_guest", &config.post_snapshot_guest)
+        })
+
+    }).finally(|| {
+        run_hook(config, "post_snapshot", &config.post_snapshot, &snapshot_name)
+    })?;
+
+    let slot_wait = slot.wait_time;
+
+    drop(slot);
+
+    if let Err(err) = verify_snapshot(config, &snapshot_name) {
+        log!("Verification of snapshot `{}` failed. Rotation is aborted.", snapshot_name);
+        return Err(err);
+    }
+
+    if let Some(pinned_until) = options.pinned_until {
+        pin_snapshot(&snapshot_name, pinned_until)?;
+    }
+
+    let rotation = clear_cache(config)?;
+
+    Ok(CreateSnapshotResult {
+        snapshot_name,
+        slot_wait,
+        deleted_snapshots: rotation.deleted_snapshots,
+        remaining_snapshots: rotation.remaining_snapshots,
+        duration: started_at.elapsed(),
+    })
+}
@@ -0,0 +1,458 @@
+use chrono::{DateTime, Local, TimeZone, NaiveDate, Datelike, Timelike, Duration};
+
+use crate::global::prelude::*;
+
+static MONTH_NAMES: &[&str] = &["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+static WEEKDAY_NAMES: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+static SYSTEMD_WEEKDAY_NAMES: &[&str] = &["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// How far ahead `next_after` looks before giving up on an expression that never matches (e.g. `0 0 30 2 *`).
+static MAX_LOOKAHEAD_DAYS: i64 = 366 * 5;
+
+/// A parsed 5 field cron expression (`minute hour day-of-month month day-of-week`).
+/// Evaluated in the local time zone of the host, the same way `cron` does it.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    pub expression: String,
+    pub minutes: Vec<u32>,
+    pub hours: Vec<u32>,
+    pub days_of_month: Vec<u32>,
+    pub months: Vec<u32>,
+    pub days_of_week: Vec<u32>,
+    pub day_of_month_restricted: bool,
+    pub day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+
+    pub fn parse(expression: &str) -> Result<CronSchedule> {
+
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            x => x,
+        };
+
+        let fields = expanded.split_whitespace().collect_vec();
+
+        if fields.len() != 5 {
+            return Err(CustomError::user_error(&format!(
+                "Invalid cron expression `{}`. Expected 5 fields: minute hour day-of-month month day-of-week.",
+                expression
+            )));
+        }
+
+        let mut days_of_week = parse_field(expression, fields[4], 0, 7, 0, WEEKDAY_NAMES)?
+            .into_iter()
+            .map(|x| x % 7)
+            .collect_vec();
+
+        days_of_week.sort();
+        days_of_week.dedup();
+
+        // Same as `cron`: a day field that starts with `*` is not restricted, even with a step like `*/2`.
+        // `0 0 */2 * 1` therefore fires on odd days that are Mondays, not on odd days or Mondays.
+        Ok(CronSchedule {
+            expression: expression.to_string(),
+            minutes: parse_field(expression, fields[0], 0, 59, 0, &[])?,
+            hours: parse_field(expression, fields[1], 0, 23, 0, &[])?,
+            days_of_month: parse_field(expression, fields[2], 1, 31, 0, &[])?,
+            day_of_month_restricted: !fields[2].starts_with('*'),
+            day_of_week_restricted: !fields[4].starts_with('*'),
+            months: parse_field(expression, fields[3], 1, 12, 1, MONTH_NAMES)?,
+            days_of_week,
+        })
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+
+        let day_of_month_match = self.days_of_month.contains(&date.day());
+        let day_of_week_match = self.days_of_week.contains(&date.weekday().num_days_from_sunday());
+
+        // Same as `cron`: when both day fields are restricted, matching either one is enough.
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month_match || day_of_week_match
+        } else {
+            day_of_month_match && day_of_week_match
+        }
+    }
+
+    /// Converts the expression to systemd `OnCalendar=` values.
+    /// systemd requires both day fields to match, which is what `cron` does unless both are restricted.
+    /// In that case `cron` requires either one to match, so one value is returned for each day field
+    /// and the timer fires when either of them elapses.
+    pub fn to_systemd_calendar(&self) -> Vec<String> {
+
+        let time = format!(
+            "{}:{}:00",
+            format_systemd_field(&self.hours, 24),
+            format_systemd_field(&self.minutes, 60)
+        );
+
+        let months = format_systemd_field(&self.months, 12);
+        let days_of_month = format_systemd_field(&self.days_of_month, 31);
+
+        let days_of_week = if self.days_of_week.len() == 7 {
+            String::new()
+        } else {
+            let names = self.days_of_week.iter()
+                .map(|x| SYSTEMD_WEEKDAY_NAMES[*x as usize])
+                .collect_vec()
+                .join(",");
+
+            format!("{} ", names)
+        };
+
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            vec![
+                format!("*-{}-{} {}", months, days_of_month, time),
+                format!("{}*-{}-* {}", days_of_week, months, time),
+            ]
+        } else {
+            vec![format!("{}*-{}-{} {}", days_of_week, months, days_of_month, time)]
+        }
+    }
+
+    /// Returns the first time strictly after `after` that matches the expression.
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = start + Duration::days(MAX_LOOKAHEAD_DAYS);
+
+        let mut candidate = start;
+
+        while candidate < limit {
+
+            let date = candidate.date();
+
+            if !self.months.contains(&date.month()) {
+                candidate = first_day_of_next_month(date)?.and_hms(0, 0, 0);
+                continue;
+            }
+
+            if !self.matches_date(date) {
+                candidate = date.succ_opt()?.and_hms(0, 0, 0);
+                continue;
+            }
+
+            if !self.hours.contains(&candidate.hour()) {
+                candidate = candidate.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+
+            if !self.minutes.contains(&candidate.minute()) {
+                candidate = candidate + Duration::minutes(1);
+                continue;
+            }
+
+            // Local times that fall into a DST gap do not exist and are skipped.
+            if let Some(result) = Local.from_local_datetime(&candidate).earliest() {
+                return Some(result);
+            }
+
+            candidate = candidate + Duration::minutes(1);
+        }
+
+        None
+    }
+}
+
+fn first_day_of_next_month(date: NaiveDate) -> Option<NaiveDate> {
+
+    if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+    }
+}
+
+fn format_systemd_field(values: &[u32], range_length: usize) -> String {
+
+    if values.len() == range_length {
+        return "*".to_string();
+    }
+
+    values.iter()
+        .map(|x| format!("{:02}", x))
+        .collect_vec()
+        .join(",")
+}
+
+fn parse_field(expression: &str, field: &str, min: u32, max: u32, name_offset: u32, names: &[&str]) -> Result<Vec<u32>> {
+
+    let invalid_field_error = || CustomError::user_error(&format!(
+        "Invalid cron expression `{}`. Cannot parse field `{}`.",
+        expression,
+        field
+    ));
+
+    let parse_value = |value: &str| -> Result<u32> {
+
+        let upper = value.to_uppercase();
+
+        if let Some(index) = names.iter().position(|x| *x == upper) {
+            return Ok(index as u32 + name_offset);
+        }
+
+        let number = value.parse::<u32>().replace_error(invalid_field_error)?;
+
+        if number < min || number > max {
+            return Err(invalid_field_error());
+        }
+
+        Ok(number)
+    };
+
+    let mut values = Vec::new();
+
+    for part in field.split(',') {
+
+        let (range, step) = match part.find('/') {
+            Some(index) => {
+                let step = part[index + 1..].parse::<u32>().replace_error(invalid_field_error)?;
+
+                if step == 0 {
+                    return Err(invalid_field_error());
+                }
+
+                (&part[..index], step)
+            },
+            None => (part, 1),
+        };
+
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some(index) = range.find('-') {
+            (parse_value(&range[..index])?, parse_value(&range[index + 1..])?)
+        } else {
+            let value = parse_value(range)?;
+
+            // `5/15` means "from 5 to the end of the range every 15".
+            if part.contains('/') { (value, max) } else { (value, value) }
+        };
+
+        if from > to {
+            return Err(invalid_field_error());
+        }
+
+        let mut value = from;
+
+        while value <= to {
+            values.push(value);
+            value += step;
+        }
+    }
+
+    values.sort();
+    values.dedup();
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Local, TimeZone};
+
+    use super::CronSchedule;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(year, month, day).and_hms(hour, minute, 0)
+    }
+
+    fn parse(expression: &str) -> CronSchedule {
+        CronSchedule::parse(expression).unwrap()
+    }
+
+    fn next_runs(expression: &str, after: DateTime<Local>, count: usize) -> Vec<DateTime<Local>> {
+
+        let schedule = parse(expression);
+
+        let mut runs = Vec::new();
+        let mut current = after;
+
+        while runs.len() < count {
+            match schedule.next_after(&current) {
+                Some(next) => {
+                    runs.push(next);
+                    current = next;
+                },
+                None => break,
+            }
+        }
+
+        runs
+    }
+
+    #[test]
+    fn parses_values_ranges_and_steps() {
+
+        let schedule = parse("5,10 */6 1-3 1-12/4 *");
+
+        assert_eq!(schedule.minutes, vec![5, 10]);
+        assert_eq!(schedule.hours, vec![0, 6, 12, 18]);
+        assert_eq!(schedule.days_of_month, vec![1, 2, 3]);
+        assert_eq!(schedule.months, vec![1, 5, 9]);
+        assert_eq!(schedule.days_of_week, vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn a_single_value_with_a_step_runs_to_the_end_of_the_range() {
+        assert_eq!(parse("50/5 * * * *").minutes, vec![50, 55]);
+    }
+
+    #[test]
+    fn parses_month_and_weekday_names() {
+
+        let schedule = parse("0 0 * jan,Mar-may mon-FRI");
+
+        assert_eq!(schedule.months, vec![1, 3, 4, 5]);
+        assert_eq!(schedule.days_of_week, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn sunday_can_be_written_as_seven() {
+        assert_eq!(parse("0 0 * * 5-7").days_of_week, vec![0, 5, 6]);
+    }
+
+    #[test]
+    fn expands_macros() {
+
+        let expected = [
+            ("@yearly", "0 0 1 1 *"),
+            ("@annually", "0 0 1 1 *"),
+            ("@monthly", "0 0 1 * *"),
+            ("@weekly", "0 0 * * 0"),
+            ("@daily", "0 0 * * *"),
+            ("@midnight", "0 0 * * *"),
+            ("@hourly", "0 * * * *"),
+        ];
+
+        for (macro_expression, expression) in expected.iter() {
+
+            let after = local(2026, 3, 10, 12, 34);
+
+            assert_eq!(
+                next_runs(macro_expression, after, 3),
+                next_runs(expression, after, 3),
+                "{}",
+                macro_expression
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+
+        let invalid = [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "10-5 * * * *",
+            "a * * * *",
+            "* * * foo *",
+            "* * * * monkey",
+            "1,,2 * * * *",
+            "@reboot",
+        ];
+
+        for expression in invalid.iter() {
+            assert!(CronSchedule::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_after_is_strictly_after() {
+
+        let after = local(2026, 3, 10, 2, 0);
+
+        assert_eq!(parse("0 2 * * *").next_after(&after), Some(local(2026, 3, 11, 2, 0)));
+        assert_eq!(parse("* * * * *").next_after(&after), Some(local(2026, 3, 10, 2, 1)));
+    }
+
+    #[test]
+    fn next_after_rolls_over_the_month_and_year() {
+        assert_eq!(parse("15 4 1 * *").next_after(&local(2026, 12, 31, 23, 59)), Some(local(2027, 1, 1, 4, 15)));
+        assert_eq!(parse("0 0 29 2 *").next_after(&local(2026, 3, 1, 0, 0)), Some(local(2028, 2, 29, 0, 0)));
+    }
+
+    #[test]
+    fn either_day_field_matches_when_both_are_restricted() {
+
+        // 2026-03-01 is a Sunday.
+        let runs = next_runs("0 0 13 * 5", local(2026, 3, 1, 0, 0), 4);
+
+        assert_eq!(runs, vec![
+            local(2026, 3, 6, 0, 0),
+            local(2026, 3, 13, 0, 0),
+            local(2026, 3, 20, 0, 0),
+            local(2026, 3, 27, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn both_day_fields_match_when_one_starts_with_a_star() {
+
+        // Odd days that are Mondays, not odd days or Mondays.
+        let runs = next_runs("0 0 */2 * 1", local(2026, 3, 1, 0, 0), 3);
+
+        assert_eq!(runs, vec![
+            local(2026, 3, 9, 0, 0),
+            local(2026, 3, 23, 0, 0),
+            local(2026, 4, 13, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn a_full_range_without_a_star_is_still_restricted() {
+
+        let schedule = parse("0 0 1-31 * 1");
+
+        assert!(schedule.day_of_month_restricted);
+        assert!(schedule.day_of_week_restricted);
+        assert_eq!(schedule.next_after(&local(2026, 3, 1, 0, 0)), Some(local(2026, 3, 2, 0, 0)));
+    }
+
+    #[test]
+    fn weekdays_only() {
+
+        // 2026-03-06 is a Friday.
+        let runs = next_runs("0 3 * * MON-FRI", local(2026, 3, 6, 12, 0), 2);
+
+        assert_eq!(runs, vec![local(2026, 3, 9, 3, 0), local(2026, 3, 10, 3, 0)]);
+    }
+
+    #[test]
+    fn an_expression_that_never_matches_returns_none() {
+        assert_eq!(parse("0 0 30 2 *").next_after(&local(2026, 1, 1, 0, 0)), None);
+        assert_eq!(parse("0 0 31 4,6,9,11 *").next_after(&local(2026, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn converts_to_systemd_calendar() {
+
+        let expected: &[(&str, &[&str])] = &[
+            ("*/15 * * * *", &["*-*-* *:00,15,30,45:00"]),
+            ("30 4 * * *", &["*-*-* 04:30:00"]),
+            ("0 3 * * MON-FRI", &["Mon,Tue,Wed,Thu,Fri *-*-* 03:00:00"]),
+            ("0 0 1 1,7 *", &["*-01,07-01 00:00:00"]),
+            ("0 0 */10 * 1", &["Mon *-*-01,11,21,31 00:00:00"]),
+            ("30 4 1,15 * 5", &["*-*-01,15 04:30:00", "Fri *-*-* 04:30:00"]),
+            ("0 0 1 * 0-6", &["*-*-01 00:00:00", "*-*-* 00:00:00"]),
+            ("@weekly", &["Sun *-*-* 00:00:00"]),
+        ];
+
+        for (expression, calendar) in expected.iter() {
+            assert_eq!(parse(expression).to_systemd_calendar(), calendar.to_vec(), "{}", expression);
+        }
+    }
+}
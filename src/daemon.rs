@@ -0,0 +1,308 @@
+use std::time::Duration;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::global::prelude::*;
+use crate::global::error_handler::report_error;
+use crate::global::notifier::NotificationEvent;
+use crate::global::state_file::{read_state, write_state};
+use crate::cron_schedule::CronSchedule;
+use crate::create_snapshot::{create_snapshot, CreateSnapshotOptions};
+use crate::blackout::blackout_reason;
+use crate::heartbeat::{ping_heartbeat, HeartbeatSignal};
+use crate::digest::{send_digest, default_digest_period_hours};
+use crate::status_server::start_status_server;
+
+/// The longest the daemon sleeps between checks, so that clock changes are picked up.
+static MAX_SLEEP_SECONDS: i64 = 60;
+
+static DAEMON_STATE_FILE_NAME: &str = "daemon-state.json";
+static DEFAULT_CATCH_UP_LIMIT: u32 = 1;
+
+/// Missed runs are not counted past this number, schedules like `* * * * *` would take forever otherwise.
+static MAX_MISSED_RUNS_COUNTED: u32 = 10000;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DaemonState {
+    last_runs: HashMap<String, DateTime<Utc>>,
+}
+
+struct ScheduledJob {
+    config: VmConfig,
+    schedule: CronSchedule,
+    next_run: Option<DateTime<Local>>,
+    deferred: bool,
+}
+
+fn scheduled_jobs() -> Result<Vec<ScheduledJob>> {
+
+    let now = Local::now();
+
+    let mut jobs = Vec::new();
+
+    let configs = app_config().snapshot_config.as_ref()
+        .map(|x| x.values().cloned().order_by(|x| x.vm_name.clone()).collect_vec())
+        .unwrap_or_default();
+
+    for config in configs {
+
+        if let Some(expression) = &config.schedule {
+
+            let schedule = CronSchedule::parse(expression)
+                .replace_error(|| CustomError::user_error(&format!(
+                    "Invalid `schedule` for vm `{}`: `{}`.",
+                    config.vm_name,
+                    expression
+                )))?;
+
+            let next_run = schedule.next_after(&now);
+
+            jobs.push(ScheduledJob {
+                config,
+                schedule,
+                next_run,
+                deferred: false,
+            });
+        }
+    }
+
+    Ok(jobs)
+}
+
+fn digest_schedule() -> Result<Option<CronSchedule>> {
+
+    let expression = match app_config().digest_config.as_ref().and_then(|x| x.schedule.clone()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let schedule = CronSchedule::parse(&expression)
+        .replace_error(|| CustomError::user_error(&format!("Invalid digest `schedule`: `{}`.", expression)))?;
+
+    Ok(Some(schedule))
+}
+
+fn log_next_run(job: &ScheduledJob) -> Result {
+
+    match job.next_run {
+        Some(next_run) => log!("Next run for vm `{}` is at {}.", job.config.vm_name, next_run.format("%Y-%m-%d %H:%M:%S %z")),
+        None => log!("The schedule `{}` for vm `{}` never matches. The vm will not be snapshotted.", job.schedule.expression, job.config.vm_name),
+    }
+
+    Ok(())
+}
+
+/// Counts the schedule matches in `(last_run, now]`.
+fn count_missed_runs(schedule: &CronSchedule, last_run: &DateTime<Local>, now: &DateTime<Local>) -> u32 {
+
+    let mut count = 0;
+    let mut current = *last_run;
+
+    while count < MAX_MISSED_RUNS_COUNTED {
+
+        match schedule.next_after(&current) {
+            Some(next) if next <= *now => {
+                count += 1;
+                current = next;
+            },
+            _ => break,
+        }
+    }
+
+    count
+}
+
+/// Runs the jobs that were missed while the daemon was not running, at most `catch_up_limit` times per vm.
+/// The default is one run, replaying every missed run would only create near-identical snapshots
+/// that push the real history out of rotation.
+/// Vms that have no recorded run yet start counting from now.
+fn catch_up(jobs: &mut [ScheduledJob], state: &mut DaemonState) -> Result {
+
+    let catch_up_limit = app_config().daemon_config.as_ref()
+        .and_then(|x| x.catch_up_limit)
+        .unwrap_or(DEFAULT_CATCH_UP_LIMIT);
+
+    let now = Local::now();
+
+    for job in jobs.iter_mut() {
+
+        let last_run = match state.last_runs.get(&job.config.vm_name) {
+            Some(x) => x.with_timezone(&Local),
+            None => {
+                state.last_runs.insert(job.config.vm_name.clone(), now.with_timezone(&Utc));
+                write_state(DAEMON_STATE_FILE_NAME, state)?;
+                continue;
+            }
+        };
+
+        let missed_runs = count_missed_runs(&job.schedule, &last_run, &now);
+
+        if missed_runs == 0 {
+            continue;
+        }
+
+        let catch_up_runs = missed_runs.min(catch_up_limit);
+
+        log!(
+            "Vm `{}` missed {} scheduled run(s) since {}. Running {} catch-up run(s).",
+            job.config.vm_name,
+            missed_runs,
+            last_run.format("%Y-%m-%d %H:%M:%S %z"),
+            catch_up_runs
+        );
+
+        for _ in 0..catch_up_runs {
+
+            run_job_and_record(job, state)?;
+
+            if job.deferred {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the job unless a blackout window is active.
+/// In that case the job is marked as deferred and runs as soon as the window ends.
+/// The deferral is logged to the heartbeat once, without counting as a successful run.
+fn run_job_and_record(job: &mut ScheduledJob, state: &mut DaemonState) -> Result {
+
+    if let Some(reason) = blackout_reason(&job.config, false)? {
+
+        if !job.deferred {
+            log!("{} Deferring the run until the window ends.", reason);
+            ping_heartbeat(&job.config, HeartbeatSignal::Skipped(format!("{} The run is deferred until the window ends.", reason)))?;
+        }
+
+        job.deferred = true;
+
+        return Ok(());
+    }
+
+    job.deferred = false;
+
+    let started_at = Utc::now();
+
+    if let Err(err) = run_scheduled_job(&job.config) {
+        report_error(&err)?;
+    }
+
+    // Recorded only after the run, so a run that is cut short by a crash is caught up on the next start.
+    state.last_runs.insert(job.config.vm_name.clone(), started_at);
+    write_state(DAEMON_STATE_FILE_NAME, state)?;
+
+    Ok(())
+}
+
+/// Runs create + rotate for a single vm and reports the outcome.
+/// Errors are reported but not propagated, so that one failing vm does not stop the daemon.
+fn run_scheduled_job(config: &VmConfig) -> Result {
+
+    logger().clear_logs()?;
+
+    log!("Run for vm `{}` ...", config.vm_name);
+
+    match create_snapshot(config, &CreateSnapshotOptions::default()) {
+        Ok(result) => notifier::notify(&NotificationEvent::Success { vm: config, result: &result })?,
+        Err(err) => {
+            report_error(&err)?;
+            notifier::notify(&NotificationEvent::Error { error: &err, vm: Some(config) })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the digest. Errors are reported but not propagated, so a failed digest does not stop the daemon.
+fn run_scheduled_digest() -> Result {
+
+    logger().clear_logs()?;
+
+    if let Err(err) = send_digest(default_digest_period_hours()) {
+        report_error(&err)?;
+    }
+
+    Ok(())
+}
+
+pub fn daemon_command() -> Result {
+
+    let mut jobs = scheduled_jobs()?;
+
+    let digest_schedule = digest_schedule()?;
+
+    if jobs.is_empty() && digest_schedule.is_none() {
+        return Err(CustomError::user_error("No vm has a `schedule` configured."));
+    }
+
+    log!("Daemon started with {} scheduled vm(s).", jobs.len());
+
+    email_outbox::retry_outbox()?;
+
+    if let Some(address) = app_config().daemon_config.as_ref().and_then(|x| x.http_address.clone()) {
+        start_status_server(&address)?;
+    }
+
+    let mut next_digest = digest_schedule.as_ref().and_then(|x| x.next_after(&Local::now()));
+
+    if let Some(next_digest) = next_digest {
+        log!("Next digest is at {}.", next_digest.format("%Y-%m-%d %H:%M:%S %z"));
+    }
+
+    let mut state: DaemonState = read_state(DAEMON_STATE_FILE_NAME)?;
+
+    catch_up(&mut jobs, &mut state)?;
+
+    for job in jobs.iter_mut() {
+        job.next_run = job.schedule.next_after(&Local::now());
+        log_next_run(job)?;
+    }
+
+    loop {
+
+        for job in jobs.iter_mut() {
+
+            let is_scheduled = job.next_run.map(|x| x <= Local::now()).unwrap_or(false);
+
+            if !is_scheduled && !job.deferred {
+                continue;
+            }
+
+            run_job_and_record(job, &mut state)?;
+
+            if is_scheduled {
+                job.next_run = job.schedule.next_after(&Local::now());
+                log_next_run(job)?;
+            }
+        }
+
+        email_outbox::retry_outbox()?;
+
+        if let Some(schedule) = &digest_schedule {
+
+            if next_digest.map(|x| x <= Local::now()).unwrap_or(false) {
+
+                run_scheduled_digest()?;
+
+                next_digest = schedule.next_after(&Local::now());
+            }
+        }
+
+        let now = Local::now();
+
+        let sleep_seconds = jobs.iter()
+            .filter_map(|x| x.next_run)
+            .chain(next_digest)
+            .map(|x| (x - now).num_seconds())
+            .min()
+            .unwrap_or(MAX_SLEEP_SECONDS)
+            .max(1)
+            .min(MAX_SLEEP_SECONDS);
+
+        ::std::thread::sleep(Duration::from_secs(sleep_seconds as u64));
+    }
+}
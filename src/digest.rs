@@ -0,0 +1,178 @@
+use clap::Arg;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::global::prelude::*;
+use crate::global::notifier::NotificationEvent;
+use crate::snapshot_helper::list_snapshots;
+use crate::run_history::runs_since;
+
+static DEFAULT_DIGEST_PERIOD_HOURS: u64 = 24;
+
+#[derive(Serialize, Debug)]
+pub struct VmDigest {
+    pub vm_name: String,
+    pub snapshot_count: Option<usize>,
+    pub newest_snapshot: Option<String>,
+    pub newest_snapshot_date: Option<DateTime<Utc>>,
+    pub newest_snapshot_age: Option<String>,
+    pub runs: usize,
+    pub failures: usize,
+    pub last_error: Option<String>,
+    pub deleted_snapshots: Vec<String>,
+    /// Set when the snapshots of the vm could not be listed.
+    pub list_error: Option<String>,
+}
+
+/// The state of every configured vm and what happened to it in the period.
+#[derive(Serialize, Debug)]
+pub struct Digest {
+    pub period_hours: u64,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub runs: usize,
+    pub failures: usize,
+    pub vms: Vec<VmDigest>,
+}
+
+/// Formats a duration as `2d 3h`, `5h 12m` or `12m`.
+pub fn format_age(age: Duration) -> String {
+
+    let minutes = age.num_minutes().max(0);
+
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+pub fn default_digest_period_hours() -> u64 {
+
+    app_config().digest_config.as_ref()
+        .and_then(|x| x.period_hours)
+        .unwrap_or(DEFAULT_DIGEST_PERIOD_HOURS)
+}
+
+pub fn build_digest(period_hours: u64) -> Result<Digest> {
+
+    let period_end = Utc::now();
+    let period_start = period_end - Duration::hours(period_hours as i64);
+
+    let runs = runs_since(period_start)?;
+
+    let configs = app_config().snapshot_config.as_ref()
+        .map(|x| x.values().cloned().order_by(|x| x.vm_name.clone()).collect_vec())
+        .unwrap_or_default();
+
+    let mut vms = Vec::new();
+
+    for config in configs {
+
+        let vm_runs = runs.iter().filter(|x| x.vm_name == config.vm_name).collect_vec();
+
+        let (snapshots, list_error) = match list_snapshots(&config) {
+            Ok(x) => (Some(x), None),
+            Err(err) => (None, Some(err.kind.to_string())),
+        };
+
+        let newest_snapshot = snapshots.as_ref()
+            .and_then(|x| x.iter().max_by_key(|y| y.date));
+
+        vms.push(VmDigest {
+            vm_name: config.vm_name.clone(),
+            snapshot_count: snapshots.as_ref().map(|x| x.len()),
+            newest_snapshot: newest_snapshot.map(|x| x.snapsnot_name.clone()),
+            newest_snapshot_date: newest_snapshot.map(|x| x.date),
+            newest_snapshot_age: newest_snapshot.map(|x| format_age(period_end - x.date)),
+            runs: vm_runs.len(),
+            failures: vm_runs.iter().filter(|x| !x.success).count(),
+            last_error: vm_runs.iter().rev().filter_map(|x| x.error.clone()).next(),
+            deleted_snapshots: vm_runs.iter().flat_map(|x| x.deleted_snapshots.clone()).collect_vec(),
+            list_error,
+        });
+    }
+
+    Ok(Digest {
+        period_hours,
+        period_start,
+        period_end,
+        runs: vms.iter().map(|x| x.runs).sum(),
+        failures: vms.iter().map(|x| x.failures).sum(),
+        vms,
+    })
+}
+
+/// Builds the digest, logs it and sends it through the notifiers.
+pub fn send_digest(period_hours: u64) -> Result {
+
+    let digest = build_digest(period_hours)?;
+
+    log!(
+        "Digest for the last {} hour(s): {} run(s), {} failure(s).",
+        digest.period_hours,
+        digest.runs,
+        digest.failures
+    );
+
+    for vm in &digest.vms {
+        log!(
+            "    {}: {} snapshot(s), newest {}, {} run(s), {} failure(s), {} deleted",
+            vm.vm_name,
+            vm.snapshot_count.map(|x| x.to_string()).unwrap_or_else(|| "?".to_string()),
+            vm.newest_snapshot_age.as_ref().map(|x| format!("{} old", x)).unwrap_or_else(|| "none".to_string()),
+            vm.runs,
+            vm.failures,
+            vm.deleted_snapshots.len()
+        );
+    }
+
+    notifier::notify(&NotificationEvent::Digest { digest: &digest })?;
+
+    Ok(())
+}
+
+struct DigestCommandOptions {
+    period_hours: u64,
+}
+
+fn digest_command_options() -> Result<DigestCommandOptions> {
+
+    const PERIOD_HOURS_VALUE: &str = "period-hours";
+
+    let matches = cli().command_config(|x| {
+
+        x.arg(Arg::with_name(PERIOD_HOURS_VALUE)
+            .short("p")
+            .long(PERIOD_HOURS_VALUE)
+            .value_name(PERIOD_HOURS_VALUE)
+            .help("How many hours back the digest covers.")
+            .takes_value(true)
+        )
+    });
+
+    let period_hours = match matches.value_of(PERIOD_HOURS_VALUE) {
+        Some(x) => x.parse::<u64>()
+            .replace_error(|| CustomError::user_error(&format!("Invalid value for: {}", PERIOD_HOURS_VALUE)))?,
+        None => default_digest_period_hours(),
+    };
+
+    Ok(DigestCommandOptions {
+        period_hours,
+    })
+}
+
+pub fn digest_command() -> Result {
+
+    let options = digest_command_options()?;
+
+    email_outbox::retry_outbox()?;
+
+    send_digest(options.period_hours)?;
+
+    Ok(())
+}
@@ -1,21 +1,221 @@
 use serde::{Serialize, Deserialize};
+use chrono::{NaiveTime, NaiveDate};
 
 use super::prelude::*;
 use std::collections::HashMap;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransport {
+    Smtp,
+    Sendmail,
+    File,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    None,
+    Starttls,
+    Tls,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmailConfig {
     pub notification_emails: Vec<String>,
-    pub smtp_username: String,
-    pub smtp_password: String,
-    pub smtp_host: String,
-    pub smtp_port: u16,
+    pub transport: Option<EmailTransport>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub security: Option<SmtpSecurity>,
+    pub auth_mechanism: Option<SmtpAuthMechanism>,
+    pub ca_file: Option<String>,
+    pub from: Option<String>,
+    pub sendmail_command: Option<String>,
+    pub file_directory: Option<String>,
+}
+
+/// A notification channel. Selected by the `type` field, e.g. `{ "type": "email" }`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    Email,
+    Webhook(WebhookConfig),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub secret: Option<String>,
+    pub signature_header: Option<String>,
+    pub timeout_seconds: Option<u64>,
+    pub retry_count: Option<u32>,
+    pub retry_delay_seconds: Option<u64>,
+    pub payload_template_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationMode {
+    Always,
+    FailuresOnly,
+    OnChange,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationPolicyConfig {
+    pub mode: Option<NotificationMode>,
+    pub failure_repeat_hours: Option<u64>,
+}
+
+/// After `after_failures` consecutive failed runs of a vm its reports are also sent to `notification_emails`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EscalationConfig {
+    pub after_failures: u32,
+    pub notification_emails: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlackoutWindow {
+    pub name: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub weekdays: Option<Vec<String>>,
+    pub dates: Option<Vec<String>>,
+}
+
+static WEEKDAY_NAMES: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+static WEEKDAY_ABBREVIATIONS: &[&str] = &["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl BlackoutWindow {
+
+    /// The start and end of the window, if it has a time range.
+    pub fn time_range(&self) -> Result<Option<(NaiveTime, NaiveTime)>> {
+
+        match (&self.start_time, &self.end_time) {
+            (Some(start_time), Some(end_time)) => Ok(Some((parse_blackout_time(start_time)?, parse_blackout_time(end_time)?))),
+            (None, None) => Ok(None),
+            _ => Err(CustomError::user_error("A blackout window needs both `start_time` and `end_time` or neither.")),
+        }
+    }
+
+    pub fn parsed_dates(&self) -> Result<Option<Vec<NaiveDate>>> {
+
+        self.dates.as_ref()
+            .map(|x| x.iter().map(|y| parse_blackout_date(y)).collect::<Result<Vec<NaiveDate>>>())
+            .transpose()
+    }
+
+    /// The weekdays of the window as days from Monday.
+    pub fn parsed_weekdays(&self) -> Result<Option<Vec<u32>>> {
+
+        self.weekdays.as_ref()
+            .map(|x| x.iter().map(|y| parse_blackout_weekday(y)).collect::<Result<Vec<u32>>>())
+            .transpose()
+    }
+
+    fn validate(&self) -> Result {
+
+        self.time_range()?;
+        self.parsed_dates()?;
+        self.parsed_weekdays()?;
+
+        Ok(())
+    }
+}
+
+fn parse_blackout_time(value: &str) -> Result<NaiveTime> {
+
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .replace_error(|| CustomError::user_error(&format!("Invalid blackout time `{}`. Expected `HH:MM`.", value)))
+}
+
+fn parse_blackout_date(value: &str) -> Result<NaiveDate> {
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .replace_error(|| CustomError::user_error(&format!("Invalid blackout date `{}`. Expected `YYYY-MM-DD`.", value)))
+}
+
+/// Accepts the full weekday name or its three letter abbreviation, in any case.
+fn parse_blackout_weekday(value: &str) -> Result<u32> {
+
+    let lower = value.to_lowercase();
+
+    WEEKDAY_NAMES.iter()
+        .position(|x| *x == lower)
+        .or_else(|| WEEKDAY_ABBREVIATIONS.iter().position(|x| *x == lower))
+        .map(|x| x as u32)
+        .ok_or_else(|| CustomError::user_error(&format!(
+            "Invalid blackout weekday `{}`. Expected a weekday name like `monday` or `mon`.",
+            value
+        )))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HookConfig {
+    pub command: String,
+    pub timeout_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuestCommandConfig {
+    pub path: String,
+    pub args: Option<Vec<String>>,
+    pub timeout_seconds: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VmConfig {
     pub vm_name: String,
     pub min_snapshot_count: i32,
+    pub verify_with_qemu_img: Option<bool>,
+    pub schedule: Option<String>,
+    pub blackout: Option<Vec<BlackoutWindow>>,
+    pub pre_snapshot: Option<HookConfig>,
+    pub post_snapshot: Option<HookConfig>,
+    pub pre_delete: Option<HookConfig>,
+    pub post_delete: Option<HookConfig>,
+    pub pre_snapshot_guest: Option<Vec<GuestCommandConfig>>,
+    pub post_snapshot_guest: Option<Vec<GuestCommandConfig>>,
+    pub heartbeat_url: Option<String>,
+    pub notification_policy: Option<NotificationPolicyConfig>,
+    pub notification_emails: Option<Vec<String>>,
+    pub escalation: Option<EscalationConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonConfig {
+    /// How many of the runs that were missed while the daemon was down are run on start. Defaults to 1.
+    pub catch_up_limit: Option<u32>,
+    /// Where the status server listens, e.g. `127.0.0.1:9477`. It is not started without it.
+    pub http_address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DigestConfig {
+    pub schedule: Option<String>,
+    pub period_hours: Option<u64>,
+}
+
+/// The node_exporter textfile collector file that the per-vm gauges are written to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsConfig {
+    pub textfile_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuardConfig {
+    pub pin_hours: Option<u64>,
+    /// How long the guard snapshot stays pinned while the command runs, on top of `pin_hours`. Defaults to 24.
+    pub max_command_hours: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,6 +224,16 @@ pub struct AppConfig {
     pub sentry_dsn: String,
     pub email_config: EmailConfig,
     pub snapshot_config: Option<HashMap<String, VmConfig>>,
+    pub daemon_config: Option<DaemonConfig>,
+    pub lock_timeout_seconds: Option<u64>,
+    pub max_concurrent_snapshots: Option<u32>,
+    pub blackout: Option<Vec<BlackoutWindow>>,
+    pub guard_config: Option<GuardConfig>,
+    pub notifiers: Option<Vec<NotifierConfig>>,
+    pub notification_policy: Option<NotificationPolicyConfig>,
+    pub digest_config: Option<DigestConfig>,
+    pub escalation: Option<EscalationConfig>,
+    pub metrics_config: Option<MetricsConfig>,
 }
 
 
@@ -31,7 +241,23 @@ pub fn read_config(file_path: &str) -> Result<AppConfig> {
 
     let json_content = ::std::fs::read_to_string(file_path)?;
 
-    let materialized = ::serde_json::from_str(&json_content)?;
+    let materialized: AppConfig = ::serde_json::from_str(&json_content)?;
+
+    if materialized.max_concurrent_snapshots == Some(0) {
+        return Err(CustomError::user_error("`max_concurrent_snapshots` must be at least 1."));
+    }
+
+    for window in materialized.blackout.iter().flat_map(|x| x.iter()) {
+        window.validate()
+            .map_err(|err| CustomError::user_error(&format!("Invalid global `blackout`: {}", err.kind.to_string())))?;
+    }
+
+    for config in materialized.snapshot_config.iter().flat_map(|x| x.values()) {
+        for window in config.blackout.iter().flat_map(|x| x.iter()) {
+            window.validate()
+                .map_err(|err| CustomError::user_error(&format!("Invalid `blackout` for vm `{}`: {}", config.vm_name, err.kind.to_string())))?;
+        }
+    }
 
     Ok(materialized)
 }
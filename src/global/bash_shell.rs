@@ -1,18 +1,77 @@
-use std::process::{Command, Stdio};
+use std::process::{Command, Stdio, ExitStatus, Child};
+use std::os::unix::process::CommandExt;
 use std::thread::JoinHandle;
 use std::thread;
+use std::time::{Duration, Instant};
 use std::io::{BufReader, Write, BufRead};
 
+use libc::{kill, SIGKILL};
+
 use super::prelude::*;
 
-fn exec_internal(command: &str, log_output: bool) -> Result<CommandResult> {
+pub struct ExecOptions {
+    pub log_output: bool,
+    pub timeout: Option<Duration>,
+    pub env: Vec<(String, String)>,
+}
+
+#[allow(unsafe_code)]
+fn kill_process_group(process: &Child) {
+
+    unsafe {
+        kill(-(process.id() as i32), SIGKILL);
+    }
+}
+
+/// Waits for the process to exit.
+/// Returns `None` if the timeout expired, in which case the whole process group is killed.
+fn wait_with_timeout(process: &mut Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+
+    let timeout = match timeout {
+        Some(x) => x,
+        None => return Ok(Some(process.wait()?)),
+    };
+
+    let start = Instant::now();
+
+    loop {
+
+        if let Some(exit_status) = process.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+
+        if start.elapsed() >= timeout {
+
+            kill_process_group(process);
+
+            process.wait()?;
+
+            return Ok(None);
+        }
 
-    let mut process = Command::new("/usr/bin/env")
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn exec_internal(command: &str, options: &ExecOptions) -> Result<CommandResult> {
+
+    let log_output = options.log_output;
+
+    let mut process_command = Command::new("/usr/bin/env");
+
+    process_command
         .arg("bash")
+        .envs(options.env.iter().map(|(key, value)| (key, value)))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .stdin(Stdio::piped())
-        .spawn()?;
+        .stdin(Stdio::piped());
+
+    // A separate process group lets a timeout kill everything the command started.
+    if options.timeout.is_some() {
+        process_command.process_group(0);
+    }
+
+    let mut process = process_command.spawn()?;
 
     let stdout = process.stdout.take()
         .or_error("stdout was not redirected.")?;
@@ -65,17 +124,18 @@ fn exec_internal(command: &str, log_output: bool) -> Result<CommandResult> {
     stdin.write_all(format!("{}\n", command).as_bytes())?;
     stdin.write_all("exit $?;\n".as_bytes())?;
 
+    let exit_status = wait_with_timeout(&mut process, options.timeout)?;
+
     let out_result = stdout_thread.join()
         .on_error("The stdout thread failed for some reason.")??;
 
     let err_result = stderr_thread.join()
         .on_error("The stderr thread failed for some reason.")??;
 
-    let exit_status = process.wait()?;
-
     return Ok(CommandResult {
-        status_code: exit_status.code(),
-        success: exit_status.success(),
+        status_code: exit_status.and_then(|x| x.code()),
+        success: exit_status.map(|x| x.success()).unwrap_or(false),
+        timed_out: exit_status.is_none(),
         stdout: out_result,
         stderr: err_result,
         command: command.to_string()
@@ -84,15 +144,34 @@ fn exec_internal(command: &str, log_output: bool) -> Result<CommandResult> {
 
 pub fn exec(command: &str) -> Result<CommandResult> {
 
-    exec_internal(command, true)
+    exec_internal(command, &ExecOptions {
+        log_output: true,
+        timeout: None,
+        env: Vec::new(),
+    })
 }
 
 pub fn exec_without_log(command: &str) -> Result<CommandResult> {
 
-    exec_internal(command, false)
+    exec_internal(command, &ExecOptions {
+        log_output: false,
+        timeout: None,
+        env: Vec::new(),
+    })
+}
+
+pub fn exec_with_options(command: &str, options: &ExecOptions) -> Result<CommandResult> {
+
+    exec_internal(command, options)
 }
 
 
+/// Quotes a value so that bash passes it through as a single literal argument.
+pub fn quote(value: &str) -> String {
+
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
 #[derive(Debug)]
 pub struct CommandResult {
     pub status_code: Option<i32>,
@@ -100,6 +179,7 @@ pub struct CommandResult {
     pub stderr: String,
     pub command: String,
     pub success: bool,
+    pub timed_out: bool,
 }
 
 impl CommandResult {
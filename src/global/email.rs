@@ -1,68 +1,168 @@
+use serde::{Serialize, Deserialize};
+
 use super::prelude::*;
+use super::app_config::{EmailConfig, EmailTransport, SmtpSecurity, SmtpAuthMechanism};
 
 use lettre_email::Email;
-use lettre::{SmtpClient, ClientSecurity, Transport};
+use lettre::{SmtpClient, SendmailTransport, SendableEmail, ClientSecurity, ClientTlsParameters, Transport};
 use lettre::smtp::authentication::{Credentials, Mechanism};
+use lettre::smtp::client::net::DEFAULT_TLS_PROTOCOLS;
+use native_tls::{TlsConnector, Certificate};
+
+static DEFAULT_SMTP_PORT: u16 = 25;
+static DEFAULT_SENDMAIL_COMMAND: &str = "/usr/sbin/sendmail";
+static DEFAULT_FILE_DIRECTORY: &str = "mail";
 
 pub struct EmailClient {
-    username: String,
-    password: String,
-    smtp_host: String,
-    smtp_port: u16,
+    config: EmailConfig,
 }
 
 impl EmailClient {
 
-    pub fn new(username: &str,
-               password: &str,
-               smtp_host: &str,
-               smtp_port: u16,
-    ) -> EmailClient
-    {
+    pub fn new(config: &EmailConfig) -> EmailClient {
         EmailClient {
-            username: username.to_string(),
-            password: password.to_string(),
-            smtp_host: smtp_host.to_string(),
-            smtp_port,
+            config: config.clone(),
         }
     }
 
-    pub fn send(self, message: &EmailMessage) -> Result {
+    fn tls_parameters(&self, smtp_host: &str) -> Result<ClientTlsParameters> {
+
+        let mut connector_builder = TlsConnector::builder();
+
+        connector_builder.min_protocol_version(Some(DEFAULT_TLS_PROTOCOLS[0]));
+
+        if let Some(ca_file) = &self.config.ca_file {
+
+            let pem = ::std::fs::read(config_directory().join(ca_file))?;
+
+            connector_builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+
+        Ok(ClientTlsParameters::new(smtp_host.to_string(), connector_builder.build()?))
+    }
 
-        let address = (&*self.smtp_host, self.smtp_port);
+    /// `security` defaults to `none`, `auth_mechanism` to `plain` and `smtp_port` to 25.
+    /// Authenticates only if both `smtp_username` and `smtp_password` are set.
+    fn send_smtp(&self, email: SendableEmail) -> Result {
 
-        let credentials = Credentials::new(
-            self.username.clone(),
-            self.password.clone()
-        );
+        let smtp_host = self.config.smtp_host.as_ref()
+            .or_error("`email_config.smtp_host` is required for the `smtp` transport.")?;
 
-        let mut smtp_client = SmtpClient::new(address,ClientSecurity::None)?
-            .credentials(credentials)
+        let address = (&**smtp_host, self.config.smtp_port.unwrap_or(DEFAULT_SMTP_PORT));
+
+        let security = match self.config.security {
+            None | Some(SmtpSecurity::None) => ClientSecurity::None,
+            Some(SmtpSecurity::Starttls) => ClientSecurity::Required(self.tls_parameters(smtp_host)?),
+            Some(SmtpSecurity::Tls) => ClientSecurity::Wrapper(self.tls_parameters(smtp_host)?),
+        };
+
+        let mechanism = match self.config.auth_mechanism {
+            None | Some(SmtpAuthMechanism::Plain) => Mechanism::Plain,
+            Some(SmtpAuthMechanism::Login) => Mechanism::Login,
+        };
+
+        let mut smtp_client = SmtpClient::new(address, security)?
             .smtp_utf8(true)
-            .authentication_mechanism(Mechanism::Plain)
-            .transport();
+            .authentication_mechanism(mechanism);
+
+        if let (Some(username), Some(password)) = (&self.config.smtp_username, &self.config.smtp_password) {
+            smtp_client = smtp_client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        smtp_client.transport().send(email)?;
+
+        Ok(())
+    }
+
+    /// Writes the message to `<file_directory>/<message id>.eml`.
+    fn write_file(&self, email: SendableEmail) -> Result {
+
+        let directory = self.config.file_directory.clone()
+            .unwrap_or_else(|| DEFAULT_FILE_DIRECTORY.to_string());
+
+        let file_path = config_directory()
+            .join(directory)
+            .create_directory()?
+            .join(format!("{}.eml", email.message_id()));
+
+        ::std::fs::write(file_path, email.message_to_string()?)?;
+
+        Ok(())
+    }
+
+    /// Sends the message through `transport`, `smtp` by default.
+    /// The sender is `from`, or `smtp_username` if `from` is not set.
+    pub fn send(self, message: &EmailMessage) -> Result {
+
+        let from = self.config.from.as_ref()
+            .or_else(|| self.config.smtp_username.as_ref())
+            .or_error("`email_config.from` is required when `email_config.smtp_username` is not set.")?;
 
         let mut email_builder = Email::builder()
-            .from(&*self.username)
-            .subject(&*message.subject)
-            .html(&*message.content);
+            .from(&**from)
+            .subject(&*message.subject);
+
+        // Messages queued in the outbox by older versions only have the html content.
+        email_builder = match &message.text_content {
+            Some(text_content) => email_builder.alternative(&*message.content, &**text_content),
+            None => email_builder.html(&*message.content),
+        };
+
+        for attachment in &message.attachments {
+            email_builder = email_builder.attachment(
+                attachment.content.as_bytes(),
+                &attachment.file_name,
+                &mime::TEXT_PLAIN_UTF_8
+            )?;
+        }
 
         for address in &message.to_addresses {
             email_builder = email_builder.bcc(&address[..]);
         }
 
-        let email= email_builder.build()?.into();
+        let email: SendableEmail = email_builder.build()?.into();
 
-        smtp_client.send(email)?;
+        match self.config.transport {
+            None | Some(EmailTransport::Smtp) => self.send_smtp(email)?,
+            Some(EmailTransport::Sendmail) => {
+                let command = self.config.sendmail_command.clone()
+                    .unwrap_or_else(|| DEFAULT_SENDMAIL_COMMAND.to_string());
+
+                SendmailTransport::new_with_command(command).send(email)?;
+            },
+            Some(EmailTransport::File) => self.write_file(email)?,
+        }
 
         Ok(())
     }
 }
 
+/// A text file attached to an email.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmailAttachment {
+    file_name: String,
+    content: String,
+}
+
+impl EmailAttachment {
+
+    pub fn new(file_name: &str, content: &str) -> EmailAttachment {
+        EmailAttachment {
+            file_name: file_name.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct EmailMessage {
     to_addresses: Vec<String>,
     content: String,
     subject: String,
+    #[serde(default)]
+    text_content: Option<String>,
+    #[serde(default)]
+    attachments: Vec<EmailAttachment>,
 }
 
 impl EmailMessage {
@@ -70,11 +170,19 @@ impl EmailMessage {
     pub fn new(to_address: Vec<String>,
                subject: &str,
                content: &str,
+               text_content: &str,
+               attachments: Vec<EmailAttachment>,
                ) -> EmailMessage {
         EmailMessage {
             to_addresses: to_address,
             content: content.to_string(),
             subject: subject.to_string(),
+            text_content: Some(text_content.to_string()),
+            attachments,
         }
     }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
 }
@@ -0,0 +1,173 @@
+use std::fs;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+
+use super::prelude::*;
+use super::email::{EmailClient, EmailMessage};
+use super::file_lock::lock_file;
+use super::error_handler::handle_error;
+
+static OUTBOX_DIRECTORY_NAME: &str = "outbox";
+static OUTBOX_LOCK_FILE_NAME: &str = "outbox.lock";
+
+static FIRST_RETRY_DELAY_SECONDS: i64 = 60;
+static MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+/// Queued emails older than this are dropped.
+static MAX_OUTBOX_MESSAGE_AGE_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OutboxEntry {
+    message: EmailMessage,
+    created_at: DateTime<Utc>,
+    attempts: u32,
+    next_attempt_at: DateTime<Utc>,
+    last_error: String,
+}
+
+/// Doubles with every attempt, starting at `FIRST_RETRY_DELAY_SECONDS` and capped at `MAX_RETRY_DELAY_SECONDS`.
+fn retry_delay(attempts: u32) -> Duration {
+
+    let exponent = attempts.max(1).min(16) - 1;
+
+    Duration::seconds((FIRST_RETRY_DELAY_SECONDS << exponent).min(MAX_RETRY_DELAY_SECONDS))
+}
+
+fn write_entry(file_path: &str, entry: &OutboxEntry) -> Result {
+
+    let temp_file_path = format!("{}.tmp", file_path);
+
+    fs::write(&temp_file_path, serde_json::to_string_pretty(entry)?)?;
+    fs::rename(&temp_file_path, file_path)?;
+
+    Ok(())
+}
+
+/// Sends the email. If that fails the email is queued in the outbox and sent by a later run.
+/// Queued emails are retried after a successful send and at the start of every command that can notify.
+pub fn send_email(message: EmailMessage) -> Result {
+
+    if let Err(err) = EmailClient::new(&app_config().email_config).send(&message) {
+
+        let now = Utc::now();
+
+        let outbox_directory = config_directory().join(OUTBOX_DIRECTORY_NAME).create_directory()?;
+
+        let file_path = outbox_directory
+            .join(format!("{}-{}.json", now.timestamp_nanos(), ::std::process::id()))
+            .get_as_string()?;
+
+        write_entry(&file_path, &OutboxEntry {
+            message,
+            created_at: now,
+            attempts: 1,
+            next_attempt_at: now + retry_delay(1),
+            last_error: err.kind.to_string(),
+        })?;
+
+        logger().log(&format!(
+            "The email could not be sent and is queued in the outbox: {}",
+            err.kind.to_string()
+        ))?;
+
+        return Ok(());
+    }
+
+    flush_outbox()?;
+
+    Ok(())
+}
+
+/// Flushes the outbox at the start of a command that can notify, so that queued emails do not wait for
+/// the next email that is sent. That might never happen with `failures_only` or without an email notifier.
+/// A failure is reported but does not fail the command.
+pub fn retry_outbox() -> Result {
+
+    if let Err(err) = flush_outbox() {
+        handle_error(&err)?;
+    }
+
+    Ok(())
+}
+
+/// Sends the queued emails that are due, oldest first.
+/// Stops at the first failure, because the next emails would most likely fail the same way.
+/// Does nothing if another process is flushing the outbox at the moment.
+pub fn flush_outbox() -> Result {
+
+    let outbox_directory = config_directory().join(OUTBOX_DIRECTORY_NAME);
+
+    if !outbox_directory.exists() {
+        return Ok(());
+    }
+
+    let lock_file_path = outbox_directory.join(OUTBOX_LOCK_FILE_NAME).get_as_string()?;
+
+    let _lock = match lock_file(&lock_file_path)? {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let mut file_paths = fs::read_dir(&outbox_directory)?
+        .map(|x| Ok(x?.path().get_as_string()?))
+        .collect::<Result<Vec<String>>>()?
+        .into_iter()
+        .filter(|x| x.ends_with(".json"))
+        .collect_vec();
+
+    file_paths.sort();
+
+    let now = Utc::now();
+
+    for file_path in file_paths {
+
+        let mut entry: OutboxEntry = match serde_json::from_str(&fs::read_to_string(&file_path)?) {
+            Ok(x) => x,
+            Err(err) => {
+                logger().log(&format!("Setting aside invalid outbox file `{}`: {}", file_path, err))?;
+
+                fs::rename(&file_path, format!("{}.invalid", file_path))?;
+
+                continue;
+            }
+        };
+
+        if now - entry.created_at > Duration::days(MAX_OUTBOX_MESSAGE_AGE_DAYS) {
+
+            logger().log(&format!(
+                "Dropping queued email `{}` after {} attempt(s). Last error: {}",
+                entry.message.subject(),
+                entry.attempts,
+                entry.last_error
+            ))?;
+
+            fs::remove_file(&file_path)?;
+
+            continue;
+        }
+
+        if entry.next_attempt_at > now {
+            continue;
+        }
+
+        match EmailClient::new(&app_config().email_config).send(&entry.message) {
+            Ok(()) => {
+                logger().log(&format!("Sent queued email `{}`.", entry.message.subject()))?;
+
+                fs::remove_file(&file_path)?;
+            },
+            Err(err) => {
+                entry.attempts += 1;
+                entry.next_attempt_at = now + retry_delay(entry.attempts);
+                entry.last_error = err.kind.to_string();
+
+                write_entry(&file_path, &entry)?;
+
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
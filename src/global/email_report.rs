@@ -1,94 +1,309 @@
-use serde_json::json;
-use handlebars::Handlebars;
+use serde_json::{json, Value};
+use handlebars::{Handlebars, no_escape};
+use chrono::Utc;
 
-use super::email;
+use super::email::{EmailMessage, EmailAttachment};
 use super::prelude::*;
 use crate::global::logger;
 use crate::global::app_config::VmConfig;
+use crate::global::notifier::{Notifier, NotificationEvent, run_results_json};
+use crate::create_snapshot::{VmRunResult, CreateSnapshotResult};
+use crate::digest::Digest;
+use crate::run_history::consecutive_failures;
+use crate::snapshot_report::build_snapshot_report;
 
-pub fn send_error_report(error: &CustomError) -> Result {
+/// Only the end of the run log is shown in the email body, the full log is attached.
+static LOG_TAIL_LINE_COUNT: usize = 30;
 
-    let app_config = app_config();
+static TEMPLATE_DIRECTORY_NAME: &str = "templates";
 
-    let subject = format!(
-        "[FAILURE] xdxd-snapshot-rotator | An error occurred on `{}`.",
-        app_config.hostname
-    );
+/// Sends the reports as emails to `email_config.notification_emails` and to the recipients of the vms they are about.
+pub struct EmailNotifier;
 
-    let html_template = include_str!("email-template.html");
+impl Notifier for EmailNotifier {
 
-    let logs = logger().get_logs()?.join("\n");
+    fn notify(&self, event: &NotificationEvent) -> Result {
 
-    let registry = Handlebars::new();
-
-    let now = app_start_time();
+        match event {
+            NotificationEvent::Success { vm, result } => send_success_report(vm, result),
+            NotificationEvent::Summary { results } => send_summary_report(results),
+            NotificationEvent::Error { error, vm } => send_error_report(error, *vm),
+            NotificationEvent::Digest { digest } => send_digest_report(digest),
+        }
+    }
+}
 
-    let report_content = registry.render_template(
-        html_template,
-        &json!({
-            "app_config": app_config,
-            "timestamp": now.format("%+").to_string(),
-            "formatted_error": format!("{:#?}", error),
-            "logs": logs,
-         })
-    )?;
+/// The templates of a report.
+/// Each of them can be overridden by a file in `config_directory/templates/`:
+/// `<name>.html`, `<name>.txt` and `<name>.subject`.
+#[derive(Clone, Copy)]
+enum ReportTemplate {
+    Success,
+    Failure,
+    Digest,
+}
 
-    send_mail(&subject, &report_content)?;
+impl ReportTemplate {
 
-    Ok(())
+    fn name(self) -> &'static str {
+        match self {
+            ReportTemplate::Success => "success",
+            ReportTemplate::Failure => "failure",
+            ReportTemplate::Digest => "digest",
+        }
+    }
 }
 
+pub fn send_error_report(error: &CustomError, vm: Option<&VmConfig>) -> Result {
 
-pub fn send_success_report(vm: &VmConfig) -> Result {
+    let subject = match vm {
+        Some(vm) => format!(
+            "[FAILURE] xdxd-snapshot-rotator | An error occurred for vm `{}` on `{}`.",
+            vm.vm_name,
+            app_config().hostname
+        ),
+        None => format!(
+            "[FAILURE] xdxd-snapshot-rotator | An error occurred on `{}`.",
+            app_config().hostname
+        ),
+    };
 
-    let app_config = app_config();
+    let context = json!({
+        "vm": vm,
+        "error": error.kind.to_string(),
+    });
+
+    let attachments = vec![
+        EmailAttachment::new("error.txt", &format!("{:#?}", error)),
+    ];
+
+    let recipients = report_recipients(&vm.into_iter().collect_vec())?;
+
+    send_report(ReportTemplate::Failure, &subject, recipients, context, &logger().get_logs()?, attachments)?;
+
+    Ok(())
+}
+
+pub fn send_success_report(vm: &VmConfig, result: &CreateSnapshotResult) -> Result {
 
     let subject = format!(
         "[SUCCESS] xdxd-snapshot-rotator | Snapshot was created for vm `{}` on host `{}`.",
         vm.vm_name,
-        app_config.hostname
+        app_config().hostname
     );
 
-    let html_template = include_str!("email-template.html");
+    let context = json!({
+        "vm": vm,
+        "snapshot_name": result.snapshot_name,
+        "deleted_snapshots": result.deleted_snapshots,
+        "slot_wait_seconds": result.slot_wait.as_secs(),
+        "duration_seconds": result.duration.as_secs(),
+        "reports": [build_snapshot_report(vm, result)],
+    });
+
+    let recipients = report_recipients(&[vm])?;
+
+    send_report(ReportTemplate::Success, &subject, recipients, context, &logger().get_logs()?, Vec::new())?;
+
+    Ok(())
+}
+
+pub fn send_summary_report(results: &[VmRunResult]) -> Result {
+
+    let app_config = app_config();
+
+    let failed_count = results.iter().filter(|x| x.error.is_some()).count();
+
+    let skipped_count = results.iter().filter(|x| x.error.is_none() && x.skipped.is_some()).count();
+
+    let created_count = results.len() - failed_count - skipped_count;
+
+    let subject = if failed_count == 0 {
+        format!(
+            "[SUCCESS] xdxd-snapshot-rotator | Snapshots were created for {} of {} vm(s) on host `{}`.",
+            created_count,
+            results.len(),
+            app_config.hostname
+        )
+    } else {
+        format!(
+            "[FAILURE] xdxd-snapshot-rotator | Snapshot creation failed for {} of {} vm(s) on host `{}`.",
+            failed_count,
+            results.len(),
+            app_config.hostname
+        )
+    };
+
+    let context = json!({
+        "results": run_results_json(results),
+        "created_count": created_count,
+        "failed_count": failed_count,
+        "skipped_count": skipped_count,
+        "reports": results.iter().filter_map(|x| x.report.as_ref()).collect_vec(),
+    });
+
+    let template = if failed_count == 0 { ReportTemplate::Success } else { ReportTemplate::Failure };
+
+    let vms = results.iter()
+        .filter_map(|x| app_config.snapshot_config.as_ref().and_then(|y| y.get(&x.vm_name)))
+        .collect_vec();
+
+    let recipients = report_recipients(&vms)?;
+
+    send_report(template, &subject, recipients, context, &logger().get_logs()?, Vec::new())?;
+
+    Ok(())
+}
 
-    let logs = logger().get_logs()?.join("\n");
+pub fn send_digest_report(digest: &Digest) -> Result {
 
-    let registry = Handlebars::new();
+    let subject = format!(
+        "[{}] xdxd-snapshot-rotator | Digest for the last {} hour(s) on host `{}`: {} run(s), {} failure(s).",
+        if digest.failures == 0 { "DIGEST" } else { "DIGEST FAILURE" },
+        digest.period_hours,
+        app_config().hostname,
+        digest.runs,
+        digest.failures
+    );
 
-    let now = app_start_time();
+    let context = json!({
+        "digest": digest,
+    });
 
-    let report_content = registry.render_template(
-        html_template,
-        &json!({
-            "app_config": app_config,
-            "timestamp": now.format("%+").to_string(),
-            "logs": logs,
-         })
-    )?;
+    let recipients = report_recipients(&[])?;
 
-    send_mail(&subject, &report_content)?;
+    send_report(ReportTemplate::Digest, &subject, recipients, context, &[], Vec::new())?;
 
     Ok(())
 }
 
-fn send_mail(subject: &str, content: &str) -> Result {
+/// `email_config.notification_emails`, the `notification_emails` of the vms and the escalation recipients
+/// of the vms that have failed `escalation.after_failures` times in a row.
+/// The escalation of the vm is used if it has one, otherwise the global one.
+fn report_recipients(vms: &[&VmConfig]) -> Result<Vec<String>> {
 
     let app_config = app_config();
 
-    let email_client = email::EmailClient::new(
-        &app_config.email_config.smtp_username,
-        &app_config.email_config.smtp_password,
-        &app_config.email_config.smtp_host,
-        app_config.email_config.smtp_port,
-    );
+    let mut recipients = app_config.email_config.notification_emails.clone();
+
+    for vm in vms {
+
+        recipients.extend(vm.notification_emails.clone().unwrap_or_default());
+
+        let escalation = vm.escalation.as_ref().or_else(|| app_config.escalation.as_ref());
+
+        if let Some(escalation) = escalation {
+
+            let failures = consecutive_failures(&vm.vm_name)?;
+
+            if failures > 0 && failures >= escalation.after_failures {
+
+                logger().log(&format!(
+                    "Vm `{}` failed {} time(s) in a row. The report is escalated to: {}",
+                    vm.vm_name,
+                    failures,
+                    escalation.notification_emails.join(", ")
+                ))?;
+
+                recipients.extend(escalation.notification_emails.clone());
+            }
+        }
+    }
+
+    let mut unique_recipients = Vec::new();
+
+    for recipient in recipients {
+        if !unique_recipients.contains(&recipient) {
+            unique_recipients.push(recipient);
+        }
+    }
+
+    Ok(unique_recipients)
+}
+
+/// Reads `config_directory/templates/<file_name>`, `None` if it does not exist.
+fn read_template_override(file_name: &str) -> Result<Option<String>> {
+
+    let file_path = config_directory().join(TEMPLATE_DIRECTORY_NAME).join(file_name);
+
+    if !file_path.exists() {
+        return Ok(None);
+    }
+
+    let template = ::std::fs::read_to_string(&file_path)
+        .replace_error(|| CustomError::from_message(&format!(
+            "The email template `{}/{}` could not be read.",
+            TEMPLATE_DIRECTORY_NAME,
+            file_name
+        )))?;
+
+    Ok(Some(template))
+}
+
+/// Renders the html and the plain text version of the report and sends them as one multipart/alternative email.
+/// The body only shows the end of the logs, the full logs are attached as `run.log`.
+/// `subject` is used unless the subject template is overridden, the templates get it as `subject`.
+/// The templates can be user supplied, so they never get the app config with its passwords and secrets.
+fn send_report(template: ReportTemplate,
+               subject: &str,
+               recipients: Vec<String>,
+               mut context: Value,
+               logs: &[String],
+               mut attachments: Vec<EmailAttachment>) -> Result {
+
+    let app_config = app_config();
+
+    let log_tail = logs.iter()
+        .skip(logs.len().saturating_sub(LOG_TAIL_LINE_COUNT))
+        .map(|x| x.as_str())
+        .collect_vec();
+
+    context["event"] = json!(template.name());
+    context["hostname"] = json!(app_config.hostname);
+    context["timestamp"] = json!(Utc::now().format("%+").to_string());
+    context["subject"] = json!(subject);
+    context["logs"] = json!(log_tail.join("\n"));
+    context["logs_truncated"] = json!(logs.len() > log_tail.len());
+
+    let html_registry = Handlebars::new();
+
+    let mut text_registry = Handlebars::new();
+
+    text_registry.register_escape_fn(no_escape);
+
+    let subject = match read_template_override(&format!("{}.subject", template.name()))? {
+        Some(x) => text_registry.render_template(&x, &context)?
+            .lines()
+            .map(|x| x.trim())
+            .find(|x| !x.is_empty())
+            .unwrap_or(subject)
+            .to_string(),
+        None => subject.to_string(),
+    };
+
+    let html_template = read_template_override(&format!("{}.html", template.name()))?
+        .unwrap_or_else(|| include_str!("email-template.html").to_string());
+
+    let html_content = html_registry.render_template(&html_template, &context)?;
+
+    let text_template = read_template_override(&format!("{}.txt", template.name()))?
+        .unwrap_or_else(|| include_str!("email-template.txt").to_string());
+
+    let text_content = text_registry.render_template(&text_template, &context)?;
+
+    if !logs.is_empty() {
+        attachments.insert(0, EmailAttachment::new("run.log", &logs.join("\n")));
+    }
 
-    let message = email::EmailMessage::new(
-        app_config.email_config.notification_emails.clone(),
-        subject,
-        content,
+    let message = EmailMessage::new(
+        recipients,
+        &subject,
+        &html_content,
+        &text_content,
+        attachments,
     );
 
-    email_client.send(&message)?;
+    email_outbox::send_email(message)?;
 
     Ok(())
 }
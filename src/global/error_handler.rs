@@ -1,7 +1,7 @@
 use super::prelude::*;
 use super::sentry_client;
 use super::logger;
-use super::email_report;
+use super::notifier::{self, NotificationEvent};
 
 /// The default error handler.
 pub fn handle_error(error: &CustomError) -> Result {
@@ -15,13 +15,26 @@ pub fn handle_error(error: &CustomError) -> Result {
     Ok(())
 }
 
+/// Handles an error that should not stop the process, e.g. a failed scheduled run.
+/// The error is logged and sent to sentry on a best-effort basis. Sentry being unreachable is logged as well.
+pub fn report_error(error: &CustomError) -> Result {
+
+    logger().log(&format!("An error occurred: {:#?}", error))?;
+
+    if let Err(sentry_error) = sentry_client().send_error(error) {
+        logger().log(&format!("The error could not be sent to sentry: {}", sentry_error.kind.to_string()))?;
+    }
+
+    Ok(())
+}
+
 pub fn handle_fatal_error(error: &CustomError) -> Result {
 
     let standard_error_handler_result = handle_error(error);
-    let email_result = email_report::send_error_report(&error);
+    let notification_result = notifier::notify(&NotificationEvent::Error { error, vm: None });
 
     standard_error_handler_result?;
-    email_result?;
+    notification_result?;
 
     Ok(())
 }
@@ -23,6 +23,10 @@ pub enum CustomErrorKind {
     XmlError(roxmltree::Error),
     SendErrorFile(std::sync::mpsc::SendError<std::fs::File>),
     RecvError(std::sync::mpsc::RecvError),
+    Base64DecodeError(base64::DecodeError),
+    OpensslError(openssl::error::ErrorStack),
+    NativeTlsError(native_tls::Error),
+    SendmailError(lettre::sendmail::error::Error),
 }
 
 #[derive(Debug)]
@@ -51,6 +55,10 @@ impl fmt::Debug for CustomErrorKind {
             LettreEmailError(err) => return err.fmt(f),
             SendErrorFile(err) => return err.fmt(f),
             RecvError(err) => return err.fmt(f),
+            Base64DecodeError(err) => return err.fmt(f),
+            OpensslError(err) => return err.fmt(f),
+            NativeTlsError(err) => return err.fmt(f),
+            SendmailError(err) => return err.fmt(f),
         };
     }
 }
@@ -75,6 +83,10 @@ impl ToString for CustomErrorKind {
             LettreEmailError(err) => return err.to_string(),
             SendErrorFile(err) => return err.to_string(),
             RecvError(err) => return err.to_string(),
+            Base64DecodeError(err) => return err.to_string(),
+            OpensslError(err) => return err.to_string(),
+            NativeTlsError(err) => return err.to_string(),
+            SendmailError(err) => return err.to_string(),
         }
     }
 }
@@ -248,6 +260,42 @@ impl From<roxmltree::Error> for CustomError {
     }
 }
 
+impl From<base64::DecodeError> for CustomError {
+    fn from(err: base64::DecodeError) -> Self {
+        CustomError {
+            kind: Base64DecodeError(err),
+            backtrace: Backtrace::new(),
+        }
+    }
+}
+
+impl From<openssl::error::ErrorStack> for CustomError {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        CustomError {
+            kind: OpensslError(err),
+            backtrace: Backtrace::new(),
+        }
+    }
+}
+
+impl From<native_tls::Error> for CustomError {
+    fn from(err: native_tls::Error) -> Self {
+        CustomError {
+            kind: NativeTlsError(err),
+            backtrace: Backtrace::new(),
+        }
+    }
+}
+
+impl From<lettre::sendmail::error::Error> for CustomError {
+    fn from(err: lettre::sendmail::error::Error) -> Self {
+        CustomError {
+            kind: SendmailError(err),
+            backtrace: Backtrace::new(),
+        }
+    }
+}
+
 pub type Result<T = ()> = ::std::result::Result<T, CustomError>;
 
 pub trait ResultExtensionsReplaceError<R> {
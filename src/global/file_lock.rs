@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::fs::{File, OpenOptions};
 use std::os::unix::io::AsRawFd;
 
@@ -6,9 +6,14 @@ use libc::{flock, LOCK_EX, LOCK_NB, EWOULDBLOCK, __errno_location};
 
 use super::prelude::*;
 
+/// The directory in the config directory that holds the lock files.
+pub static LOCK_DIRECTORY_NAME: &str = "locks";
+
+/// Tries to take an exclusive lock on the file without blocking.
+/// Returns `None` if another process holds the lock.
 #[allow(unsafe_code)]
 #[allow(unused)]
-fn lock_file(file_path: &str) -> Result<Option<File>> {
+pub fn lock_file(file_path: &str) -> Result<Option<File>> {
 
     let file = OpenOptions::new()
         .write(true)
@@ -27,11 +32,22 @@ fn lock_file(file_path: &str) -> Result<Option<File>> {
     }
 }
 
+/// Waits until the lock on the file is taken.
+/// Returns `None` if the timeout expires first. Waits forever if no timeout is given.
 #[allow(unused)]
-pub fn wait_for_lock(file_path: &str) -> Result<File> {
+pub fn wait_for_lock(file_path: &str, timeout: Option<Duration>) -> Result<Option<File>> {
+
+    let start = Instant::now();
+
     loop {
         if let Some(file) = lock_file(file_path)? {
-            return Ok(file);
+            return Ok(Some(file));
+        }
+
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
         }
 
         std::thread::sleep(Duration::from_millis(100));
@@ -202,4 +202,15 @@ impl Logger {
 
         Ok(logs.clone())
     }
+
+    /// Drops the in-memory entries so that the next report only contains the logs of the next run.
+    #[allow(unused)]
+    pub fn clear_logs(&self) -> Result {
+
+        let mut logs = self.in_memory_appender.entries.lock()?;
+
+        logs.clear();
+
+        Ok(())
+    }
 }
@@ -12,8 +12,13 @@ pub mod bash_shell;
 pub mod do_try;
 pub mod email;
 pub mod email_report;
+pub mod email_outbox;
+pub mod notifier;
+pub mod notification_policy;
+pub mod webhook;
 pub mod cli;
 pub mod file_lock;
+pub mod state_file;
 
 use std::path::{PathBuf, Path};
 use chrono::{DateTime, Utc};
@@ -150,6 +155,12 @@ pub fn app_start_time() -> &'static DateTime<Utc> {
     &GLOBAL_INSTANCE.app_start_time
 }
 
+#[allow(unused)]
+pub fn config_directory() -> &'static PathBuf {
+
+    &GLOBAL_INSTANCE.config_directory
+}
+
 #[allow(unused)]
 pub fn cli() -> &'static CliRunner {
 
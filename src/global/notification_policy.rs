@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+
+use super::prelude::*;
+use super::app_config::{NotificationPolicyConfig, NotificationMode};
+use super::state_file::update_state;
+
+static NOTIFICATION_STATE_FILE_NAME: &str = "notification-state.json";
+
+/// The key under which outcomes that do not belong to a single vm are recorded.
+static GLOBAL_STATE_KEY: &str = "*";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct NotificationState {
+    outcomes: HashMap<String, OutcomeRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OutcomeRecord {
+    failed: bool,
+    last_failure_notification: Option<DateTime<Utc>>,
+}
+
+/// Records the outcome and decides if it should be reported.
+/// The policy of the vm is used if it has one, otherwise the global one. Without any policy everything is reported.
+///
+/// `mode` decides between `always`, `failures_only` and `on_change` (the first failure and the recovery).
+/// With `failure_repeat_hours` a failure that follows a failure is reported only if that many hours
+/// have passed since the last reported failure, regardless of `mode`.
+/// The state file is locked while the outcome is recorded, because vms run at the same time.
+pub fn should_notify(vm: Option<&VmConfig>, failed: bool) -> Result<bool> {
+
+    let policy = vm.and_then(|x| x.notification_policy.clone())
+        .or_else(|| app_config().notification_policy.clone())
+        .unwrap_or(NotificationPolicyConfig { mode: None, failure_repeat_hours: None });
+
+    let key = vm.map(|x| x.vm_name.clone()).unwrap_or_else(|| GLOBAL_STATE_KEY.to_string());
+
+    update_state(NOTIFICATION_STATE_FILE_NAME, |state: &mut NotificationState| {
+        Ok(record_outcome(state, policy, key, failed))
+    })
+}
+
+fn record_outcome(state: &mut NotificationState, policy: NotificationPolicyConfig, key: String, failed: bool) -> bool {
+
+    let previous = state.outcomes.get(&key).cloned();
+
+    let previously_failed = previous.as_ref().map(|x| x.failed).unwrap_or(false);
+
+    let now = Utc::now();
+
+    let notify = match (failed && previously_failed, policy.failure_repeat_hours) {
+        (true, Some(hours)) => previous.as_ref()
+            .and_then(|x| x.last_failure_notification)
+            .map(|x| now - x >= Duration::hours(hours as i64))
+            .unwrap_or(true),
+        _ => match policy.mode.unwrap_or(NotificationMode::Always) {
+            NotificationMode::Always => true,
+            NotificationMode::FailuresOnly => failed,
+            NotificationMode::OnChange => failed != previously_failed,
+        }
+    };
+
+    let last_failure_notification = match (failed, notify) {
+        (false, _) => None,
+        (true, true) => Some(now),
+        (true, false) => previous.and_then(|x| x.last_failure_notification),
+    };
+
+    state.outcomes.insert(key, OutcomeRecord {
+        failed,
+        last_failure_notification,
+    });
+
+    notify
+}
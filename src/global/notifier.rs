@@ -0,0 +1,170 @@
+use serde_json::{json, Value};
+
+use super::prelude::*;
+use super::app_config::NotifierConfig;
+use super::email_report::EmailNotifier;
+use super::webhook::WebhookNotifier;
+use super::notification_policy::should_notify;
+use crate::create_snapshot::{VmRunResult, CreateSnapshotResult};
+use crate::digest::Digest;
+
+/// Something that the configured notifiers report on.
+pub enum NotificationEvent<'a> {
+    Success {
+        vm: &'a VmConfig,
+        result: &'a CreateSnapshotResult,
+    },
+    Summary {
+        results: &'a [VmRunResult],
+    },
+    Error {
+        error: &'a CustomError,
+        vm: Option<&'a VmConfig>,
+    },
+    Digest {
+        digest: &'a Digest,
+    },
+}
+
+impl<'a> NotificationEvent<'a> {
+
+    /// The vm that the notification policy is looked up for, `None` for the global one.
+    fn policy_vm(&self) -> Option<&'a VmConfig> {
+        match self {
+            NotificationEvent::Success { vm, .. } => Some(vm),
+            NotificationEvent::Summary { .. } => None,
+            NotificationEvent::Error { vm, .. } => *vm,
+            NotificationEvent::Digest { .. } => None,
+        }
+    }
+
+    fn is_failure(&self) -> bool {
+        match self {
+            NotificationEvent::Success { .. } => false,
+            NotificationEvent::Summary { results } => results.iter().any(|x| x.error.is_some()),
+            NotificationEvent::Error { .. } => true,
+            NotificationEvent::Digest { .. } => false,
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            NotificationEvent::Success { .. } => "success",
+            NotificationEvent::Summary { .. } => "summary",
+            NotificationEvent::Error { .. } => "error",
+            NotificationEvent::Digest { .. } => "digest",
+        }
+    }
+}
+
+/// The per-vm results of a batch run, in the form the reports use.
+pub fn run_results_json(results: &[VmRunResult]) -> Vec<Value> {
+
+    results.iter()
+        .map(|x| json!({
+            "vm_name": x.vm_name,
+            "success": x.error.is_none() && x.skipped.is_none(),
+            "skipped": x.skipped,
+            "error": x.error,
+            "duration_seconds": (x.finished_at - x.started_at).num_seconds(),
+            "slot_wait_seconds": x.slot_wait_seconds,
+            "report": x.report,
+        }))
+        .collect_vec()
+}
+
+/// A notification channel.
+pub trait Notifier {
+
+    fn notify(&self, event: &NotificationEvent) -> Result;
+}
+
+fn create_notifier(config: &NotifierConfig) -> Box<dyn Notifier> {
+
+    match config {
+        NotifierConfig::Email => Box::new(EmailNotifier),
+        NotifierConfig::Webhook(x) => Box::new(WebhookNotifier::new(x.clone())),
+    }
+}
+
+/// Creates the notifiers listed in `notifiers`. Email is the only one when the list is not configured.
+fn configured_notifiers() -> Vec<Box<dyn Notifier>> {
+
+    match &app_config().notifiers {
+        Some(x) => x.iter().map(create_notifier).collect_vec(),
+        None => vec![create_notifier(&NotifierConfig::Email)],
+    }
+}
+
+/// Logs the error and sends it to sentry. Sentry being unreachable is logged as well.
+fn report_notification_error(error: &CustomError) -> Result {
+
+    logger().log(&format!("A notification could not be sent: {:#?}", error))?;
+
+    if let Err(sentry_error) = sentry_client().send_error(error) {
+        logger().log(&format!("The notification error could not be sent to sentry: {}", sentry_error.kind.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Asks the notification policy about the event.
+/// A batch summary is sent if the policy of any vm in it asks for a notification. Every vm is evaluated,
+/// so that each outcome is recorded. Skipped vms have no outcome.
+fn policy_allows(event: &NotificationEvent) -> Result<bool> {
+
+    let results = match event {
+        NotificationEvent::Summary { results } => results.iter().filter(|x| x.skipped.is_none()).collect_vec(),
+        _ => return should_notify(event.policy_vm(), event.is_failure()),
+    };
+
+    if results.is_empty() {
+        return should_notify(None, false);
+    }
+
+    let mut notify = false;
+
+    for result in results {
+
+        let vm = app_config().snapshot_config.as_ref().and_then(|x| x.get(&result.vm_name));
+
+        notify |= should_notify(vm, result.error.is_some())?;
+    }
+
+    Ok(notify)
+}
+
+/// Sends the event through every configured notifier, unless the notification policy suppresses it.
+/// Digests are scheduled explicitly, so the policy does not apply to them.
+/// Notification failures are reported but never returned, so they can not hide the outcome that is being reported.
+pub fn notify(event: &NotificationEvent) -> Result {
+
+    let is_digest = match event {
+        NotificationEvent::Digest { .. } => true,
+        _ => false,
+    };
+
+    if !is_digest {
+
+        let notify = match policy_allows(event) {
+            Ok(x) => x,
+            Err(err) => {
+                report_notification_error(&err)?;
+                true
+            }
+        };
+
+        if !notify {
+            logger().log("The notification is suppressed by the notification policy.")?;
+            return Ok(());
+        }
+    }
+
+    for notifier in configured_notifiers() {
+        if let Err(err) = notifier.notify(event) {
+            report_notification_error(&err)?;
+        }
+    }
+
+    Ok(())
+}
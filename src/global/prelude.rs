@@ -2,6 +2,5 @@
 pub use super::error_handler::ResultExtensionsCrashOnError;
 pub use super::errors::{Result, CustomError, ResultExtensionsReplaceError};
 pub use super::app_config::{AppConfig, VmConfig};
-pub use super::app_start_time;
 pub use super::extensions::*;
 pub use crate::global::*;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::prelude::*;
+use super::config_directory;
+use super::file_lock::{wait_for_lock, LOCK_DIRECTORY_NAME};
+
+/// Makes the temporary file names unique between the threads of a process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads a json state file from the config directory.
+/// Returns the default value if the file does not exist yet.
+#[allow(unused)]
+pub fn read_state<T: DeserializeOwned + Default>(file_name: &str) -> Result<T> {
+
+    let file_path = config_directory().join(file_name);
+
+    if !file_path.exists() {
+        return Ok(T::default());
+    }
+
+    let json_content = ::std::fs::read_to_string(&file_path)?;
+
+    let state = serde_json::from_str(&json_content)?;
+
+    Ok(state)
+}
+
+/// Writes a json state file to the config directory.
+/// The content is written to a temporary file first and renamed over the old one,
+/// so a crash in the middle of the write never leaves a truncated state file behind.
+/// The temporary file is unique to the writer, so concurrent writers never rename each other's partial files.
+#[allow(unused)]
+pub fn write_state<T: Serialize>(file_name: &str, state: &T) -> Result {
+
+    let file_path = config_directory().join(file_name);
+    let temp_file_path = config_directory().join(format!(
+        "{}.{}-{}.tmp",
+        file_name,
+        ::std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let json_content = serde_json::to_string_pretty(state)?;
+
+    ::std::fs::write(&temp_file_path, json_content)?;
+    ::std::fs::rename(&temp_file_path, &file_path)?;
+
+    Ok(())
+}
+
+/// Reads, changes and writes a state file while holding `locks/<file_name>.lock`,
+/// so that concurrent runs do not lose each other's updates.
+#[allow(unused)]
+pub fn update_state<T, R, F>(file_name: &str, update: F) -> Result<R>
+    where T: Serialize + DeserializeOwned + Default,
+          F: FnOnce(&mut T) -> Result<R> {
+
+    let lock_file_path = config_directory()
+        .join(LOCK_DIRECTORY_NAME)
+        .create_directory()?
+        .join(format!("{}.lock", file_name))
+        .get_as_string()?;
+
+    let _lock = wait_for_lock(&lock_file_path, None)?;
+
+    let mut state = read_state(file_name)?;
+
+    let result = update(&mut state)?;
+
+    write_state(file_name, &state)?;
+
+    Ok(result)
+}
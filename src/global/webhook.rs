@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use handlebars::Handlebars;
+use chrono::Utc;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use super::prelude::*;
+use super::app_config::WebhookConfig;
+use super::notifier::{Notifier, NotificationEvent, run_results_json};
+use crate::snapshot_report::build_snapshot_report;
+
+static DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+static DEFAULT_RETRY_COUNT: u32 = 3;
+static DEFAULT_RETRY_DELAY_SECONDS: u64 = 5;
+static DEFAULT_SIGNATURE_HEADER: &str = "X-Xdxd-Signature";
+
+/// POSTs a json document describing the event to `url`.
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+}
+
+impl WebhookNotifier {
+
+    pub fn new(config: WebhookConfig) -> WebhookNotifier {
+        WebhookNotifier {
+            config,
+        }
+    }
+
+    fn payload(&self, event: &NotificationEvent) -> Result<Value> {
+
+        let app_config = app_config();
+
+        let mut payload = json!({
+            "event": event.event_type(),
+            "host": app_config.hostname,
+            "timestamp": Utc::now().format("%+").to_string(),
+            "vm_name": null,
+            "snapshot_name": null,
+            "deleted_snapshots": [],
+            "results": [],
+            "error": null,
+            "logs": logger().get_logs()?,
+        });
+
+        match event {
+            NotificationEvent::Success { vm, result } => {
+                payload["vm_name"] = json!(vm.vm_name);
+                payload["snapshot_name"] = json!(result.snapshot_name);
+                payload["deleted_snapshots"] = json!(result.deleted_snapshots);
+                payload["report"] = json!(build_snapshot_report(vm, result));
+            },
+            NotificationEvent::Summary { results } => {
+                payload["results"] = json!(run_results_json(results));
+            },
+            NotificationEvent::Error { error, vm } => {
+                payload["vm_name"] = json!(vm.map(|x| x.vm_name.clone()));
+                payload["error"] = json!(error.kind.to_string());
+            },
+            NotificationEvent::Digest { digest } => {
+                payload["digest"] = json!(digest);
+            },
+        }
+
+        Ok(payload)
+    }
+
+    /// Renders the request body.
+    /// With `payload_template_file` the default payload is the context of a handlebars template
+    /// in which values are escaped as json strings, otherwise it is sent as is.
+    fn body(&self, event: &NotificationEvent) -> Result<String> {
+
+        let payload = self.payload(event)?;
+
+        let template_file = match &self.config.payload_template_file {
+            Some(x) => x,
+            None => return Ok(serde_json::to_string(&payload)?),
+        };
+
+        let template = ::std::fs::read_to_string(config_directory().join(template_file))?;
+
+        let mut registry = Handlebars::new();
+
+        registry.register_escape_fn(json_escape);
+
+        let body = registry.render_template(&template, &payload)?;
+
+        serde_json::from_str::<Value>(&body)
+            .replace_error(|| CustomError::from_message(&format!(
+                "The webhook payload template `{}` did not produce valid json.",
+                template_file
+            )))?;
+
+        Ok(body)
+    }
+
+    fn signature(&self, secret: &str, body: &str) -> Result<String> {
+
+        let key = PKey::hmac(secret.as_bytes())?;
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+
+        signer.update(body.as_bytes())?;
+
+        let signature = signer.sign_to_vec()?
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>();
+
+        Ok(format!("sha256={}", signature))
+    }
+
+    fn post(&self, client: &reqwest::Client, body: &str) -> Result {
+
+        let mut request = client
+            .post(&self.config.url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+
+        if let Some(secret) = &self.config.secret {
+
+            let header = self.config.signature_header.clone()
+                .unwrap_or_else(|| DEFAULT_SIGNATURE_HEADER.to_string());
+
+            request = request.header(&*header, &*self.signature(secret, body)?);
+        }
+
+        let response = request.send()?;
+
+        if !response.status().is_success() {
+            return Err(CustomError::from_message(&format!(
+                "The webhook `{}` responded with status {}.",
+                self.config.url,
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookNotifier {
+
+    /// Retries `retry_count` times with `retry_delay_seconds` between the attempts.
+    fn notify(&self, event: &NotificationEvent) -> Result {
+
+        let body = self.body(event)?;
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.config.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS)))
+            .build()?;
+
+        let retry_count = self.config.retry_count.unwrap_or(DEFAULT_RETRY_COUNT);
+        let retry_delay = Duration::from_secs(self.config.retry_delay_seconds.unwrap_or(DEFAULT_RETRY_DELAY_SECONDS));
+
+        let mut attempt = 0;
+
+        loop {
+
+            let err = match self.post(&client, &body) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+
+            if attempt >= retry_count {
+                return Err(err);
+            }
+
+            attempt += 1;
+
+            logger().log(&format!(
+                "{} Retrying in {} second(s) ({} of {}) ...",
+                err.kind.to_string(),
+                retry_delay.as_secs(),
+                attempt,
+                retry_count
+            ))?;
+
+            ::std::thread::sleep(retry_delay);
+        }
+    }
+}
+
+/// Escapes a value for use inside a json string literal.
+fn json_escape(value: &str) -> String {
+
+    let quoted = serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string());
+
+    quoted[1..quoted.len() - 1].to_string()
+}
@@ -0,0 +1,236 @@
+use clap::Arg;
+use chrono::{Duration, Utc};
+
+use crate::global::prelude::*;
+use crate::global::error_handler::report_error;
+use crate::global::bash_shell::{exec_with_options, ExecOptions, quote};
+use crate::global::notifier::NotificationEvent;
+use crate::create_snapshot::{create_snapshot, CreateSnapshotOptions};
+use crate::snapshot_helper::{pin_snapshot, revert_snapshot};
+use crate::blackout::blackout_reason;
+
+static DEFAULT_PIN_HOURS: u64 = 72;
+static DEFAULT_MAX_COMMAND_HOURS: u64 = 24;
+
+enum GuardFailureAction {
+    Revert,
+    Notify,
+}
+
+struct GuardCommandOptions {
+    vm_name: String,
+    label: Option<String>,
+    on_failure: GuardFailureAction,
+    pin_hours: u64,
+    force: bool,
+    command: String,
+}
+
+fn guard_command_options() -> Result<GuardCommandOptions> {
+
+    const VM_NAME_VALUE: &str = "vm-name";
+    const LABEL_VALUE: &str = "label";
+    const ON_FAILURE_VALUE: &str = "on-failure";
+    const PIN_HOURS_VALUE: &str = "pin-hours";
+    const FORCE_VALUE: &str = "force";
+    const COMMAND_VALUE: &str = "command";
+
+    let matches = cli().command_config(|x| {
+
+        x.arg(Arg::with_name(VM_NAME_VALUE)
+            .short("n")
+            .long(VM_NAME_VALUE)
+            .value_name(VM_NAME_VALUE)
+            .help("The name of virtual machine.")
+            .required(true)
+            .takes_value(true)
+        ).arg(Arg::with_name(LABEL_VALUE)
+            .short("l")
+            .long(LABEL_VALUE)
+            .value_name(LABEL_VALUE)
+            .help("Added to the snapshot name. Letters, digits, `-` and `_` only.")
+            .takes_value(true)
+        ).arg(Arg::with_name(ON_FAILURE_VALUE)
+            .long(ON_FAILURE_VALUE)
+            .value_name(ON_FAILURE_VALUE)
+            .help("What to do when the command fails.")
+            .possible_values(&["revert", "notify"])
+            .default_value("revert")
+            .takes_value(true)
+        ).arg(Arg::with_name(PIN_HOURS_VALUE)
+            .long(PIN_HOURS_VALUE)
+            .value_name(PIN_HOURS_VALUE)
+            .help("How long the snapshot is excluded from rotation after the command succeeds.")
+            .takes_value(true)
+        ).arg(Arg::with_name(FORCE_VALUE)
+            .short("f")
+            .long(FORCE_VALUE)
+            .help("Ignore blackout windows.")
+        ).arg(Arg::with_name(COMMAND_VALUE)
+            .help("The command to run, after `--`.")
+            .required(true)
+            .multiple(true)
+            .last(true)
+        )
+    });
+
+    let vm_name = matches.value_of(VM_NAME_VALUE)
+        .or_error(&format!("No value for: {}", VM_NAME_VALUE))?;
+
+    let label = matches.value_of(LABEL_VALUE).map(|x| x.to_string());
+
+    if let Some(label) = &label {
+        if label.is_empty() || !label.chars().all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_') {
+            return Err(CustomError::user_error(&format!("Invalid value for: {}", LABEL_VALUE)));
+        }
+    }
+
+    let on_failure = match matches.value_of(ON_FAILURE_VALUE) {
+        Some("notify") => GuardFailureAction::Notify,
+        _ => GuardFailureAction::Revert,
+    };
+
+    let pin_hours = match matches.value_of(PIN_HOURS_VALUE) {
+        Some(x) => x.parse::<u64>()
+            .replace_error(|| CustomError::user_error(&format!("Invalid value for: {}", PIN_HOURS_VALUE)))?,
+        None => app_config().guard_config.as_ref()
+            .and_then(|x| x.pin_hours)
+            .unwrap_or(DEFAULT_PIN_HOURS),
+    };
+
+    let command = matches.values_of(COMMAND_VALUE)
+        .or_error(&format!("No value for: {}", COMMAND_VALUE))?
+        .map(quote)
+        .collect_vec()
+        .join(" ");
+
+    Ok(GuardCommandOptions {
+        vm_name: vm_name.to_string(),
+        label,
+        on_failure,
+        pin_hours,
+        force: matches.is_present(FORCE_VALUE),
+        command,
+    })
+}
+
+/// Sets the pin of the guard snapshot to `pin_duration` from now.
+/// A failure is logged, the snapshot keeps the pin it got when it was created.
+fn repin_guard_snapshot(snapshot_name: &str, pin_duration: Duration) -> Result {
+
+    let pinned_until = Utc::now() + pin_duration;
+
+    match pin_snapshot(snapshot_name, pinned_until) {
+        Ok(()) => log!("Snapshot `{}` is pinned until {}.", snapshot_name, pinned_until.format("%Y-%m-%d %H:%M:%S UTC")),
+        Err(err) => log!("Snapshot `{}` could not be pinned again: {}", snapshot_name, err.kind.to_string()),
+    }
+
+    Ok(())
+}
+
+/// Takes a snapshot, runs the command and reverts the vm to the snapshot if the command fails.
+/// The snapshot is pinned for `max_command_hours` + `pin_hours` while the command runs,
+/// so that a guard that is killed mid-command does not leave it pinned forever,
+/// and for `pin_hours` after the command exits.
+pub fn guard_command() -> Result {
+
+    let options = guard_command_options()?;
+
+    email_outbox::retry_outbox()?;
+
+    let config = app_config().snapshot_config.as_ref()
+        .and_then(|x| x.get(&options.vm_name).cloned())
+        .or_error(&format!("``xdxd-snapshot-rotator` not configured for vm `{}`", options.vm_name))?;
+
+    if let Some(reason) = blackout_reason(&config, options.force)? {
+        return Err(CustomError::user_error(&format!("{} Use `--force` to run the command anyway.", reason)));
+    }
+
+    let label = match &options.label {
+        Some(x) => format!("guard-{}", x),
+        None => "guard".to_string(),
+    };
+
+    let pin_duration = Duration::hours(options.pin_hours as i64);
+
+    let max_command_hours = app_config().guard_config.as_ref()
+        .and_then(|x| x.max_command_hours)
+        .unwrap_or(DEFAULT_MAX_COMMAND_HOURS);
+
+    let result = create_snapshot(&config, &CreateSnapshotOptions {
+        label: Some(label),
+        pinned_until: Some(Utc::now() + Duration::hours(max_command_hours as i64) + pin_duration),
+    })?;
+
+    log!("Running guarded command for vm `{}`: {}", config.vm_name, options.command);
+
+    let ps = exec_with_options(&options.command, &ExecOptions {
+        log_output: true,
+        timeout: None,
+        env: vec![
+            ("XDXD_VM_NAME".to_string(), config.vm_name.clone()),
+            ("XDXD_SNAPSHOT_NAME".to_string(), result.snapshot_name.clone()),
+            ("XDXD_HOSTNAME".to_string(), app_config().hostname.clone()),
+        ],
+    });
+
+    let ps = match ps {
+        Ok(x) => x,
+        Err(err) => {
+            repin_guard_snapshot(&result.snapshot_name, pin_duration)?;
+            return Err(err);
+        }
+    };
+
+    if ps.success {
+
+        log!("The guarded command succeeded.");
+
+        repin_guard_snapshot(&result.snapshot_name, pin_duration)?;
+
+        notifier::notify(&NotificationEvent::Success { vm: &config, result: &result })?;
+
+        return Ok(());
+    }
+
+    let exit_code = ps.status_code.map(|x| x.to_string()).unwrap_or_else(|| "none".to_string());
+
+    log!("The guarded command failed with exit code {}.", exit_code);
+
+    let message = match options.on_failure {
+        GuardFailureAction::Revert => match revert_snapshot(&config, &result.snapshot_name) {
+            Ok(()) => format!(
+                "The guarded command `{}` failed with exit code {}. Vm `{}` was reverted to snapshot `{}`.",
+                options.command,
+                exit_code,
+                config.vm_name,
+                result.snapshot_name
+            ),
+            Err(err) => format!(
+                "The guarded command `{}` failed with exit code {}. Reverting vm `{}` to snapshot `{}` failed: {}",
+                options.command,
+                exit_code,
+                config.vm_name,
+                result.snapshot_name,
+                err.kind.to_string()
+            ),
+        },
+        GuardFailureAction::Notify => format!(
+            "The guarded command `{}` failed with exit code {}. Vm `{}` was not reverted, snapshot `{}` is available.",
+            options.command,
+            exit_code,
+            config.vm_name,
+            result.snapshot_name
+        ),
+    };
+
+    repin_guard_snapshot(&result.snapshot_name, pin_duration)?;
+
+    let error = CustomError::from_message(&message);
+
+    let report_result = report_error(&error);
+    notifier::notify(&NotificationEvent::Error { error: &error, vm: Some(&config) })?;
+    report_result?;
+
+    Err(CustomError::user_error(&format!("The guarded command failed for vm `{}`.", config.vm_name)))
+}
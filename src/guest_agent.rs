@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::global::prelude::*;
+use crate::global::app_config::GuestCommandConfig;
+use crate::global::bash_shell::quote;
+
+static DEFAULT_GUEST_COMMAND_TIMEOUT_SECONDS: u64 = 300;
+static GUEST_EXEC_STATUS_POLL_INTERVAL_MILLISECONDS: u64 = 500;
+
+pub struct GuestExecResult {
+    pub exit_code: Option<i64>,
+    pub signal: Option<i64>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Sends a command to the qemu guest agent and returns the `return` value of the response.
+fn agent_command(config: &VmConfig, command: &Value) -> Result<Value> {
+
+    let ps = bash_exec_no_log!(
+        "virsh qemu-agent-command {} {}",
+        config.vm_name,
+        quote(&serde_json::to_string(command)?)
+    );
+
+    let response: Value = serde_json::from_str(&ps.stdout)?;
+
+    let result = response.get("return").cloned()
+        .or_error(&format!("The guest agent of vm `{}` returned an unexpected response: {}", config.vm_name, ps.stdout.trim()))?;
+
+    Ok(result)
+}
+
+fn decode_output(status: &Value, field: &str) -> Result<String> {
+
+    let encoded = match status.get(field).and_then(|x| x.as_str()) {
+        Some(x) => x,
+        None => return Ok(String::new()),
+    };
+
+    let bytes = base64::decode(encoded)?;
+
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Runs a command inside the guest through `guest-exec` and polls `guest-exec-status` until it exits.
+/// The guest agent cannot kill a command, so on timeout it is left running and an error is returned.
+pub fn guest_exec(config: &VmConfig, command: &GuestCommandConfig) -> Result<GuestExecResult> {
+
+    let timeout_seconds = command.timeout_seconds.unwrap_or(DEFAULT_GUEST_COMMAND_TIMEOUT_SECONDS);
+
+    let exec_result = agent_command(config, &json!({
+        "execute": "guest-exec",
+        "arguments": {
+            "path": command.path,
+            "arg": command.args.clone().unwrap_or_default(),
+            "capture-output": true,
+        }
+    }))?;
+
+    let pid = exec_result.get("pid").and_then(|x| x.as_i64())
+        .or_error(&format!("The guest agent of vm `{}` did not return a pid for `guest-exec`.", config.vm_name))?;
+
+    let start = Instant::now();
+
+    loop {
+
+        let status = agent_command(config, &json!({
+            "execute": "guest-exec-status",
+            "arguments": {
+                "pid": pid,
+            }
+        }))?;
+
+        if status.get("exited").and_then(|x| x.as_bool()).unwrap_or(false) {
+
+            return Ok(GuestExecResult {
+                exit_code: status.get("exitcode").and_then(|x| x.as_i64()),
+                signal: status.get("signal").and_then(|x| x.as_i64()),
+                stdout: decode_output(&status, "out-data")?,
+                stderr: decode_output(&status, "err-data")?,
+            });
+        }
+
+        if start.elapsed() >= Duration::from_secs(timeout_seconds) {
+            return Err(CustomError::from_message(&format!(
+                "The guest command `{}` (pid {}) in vm `{}` timed out after {} second(s).",
+                command.path,
+                pid,
+                config.vm_name,
+                timeout_seconds
+            )));
+        }
+
+        ::std::thread::sleep(Duration::from_millis(GUEST_EXEC_STATUS_POLL_INTERVAL_MILLISECONDS));
+    }
+}
+
+/// Runs a single guest command and fails if it does not exit with 0.
+pub fn run_guest_command(config: &VmConfig, hook_name: &str, command: &GuestCommandConfig) -> Result {
+
+    log!(
+        "Running `{}` guest command in vm `{}`: {} {}",
+        hook_name,
+        config.vm_name,
+        command.path,
+        command.args.clone().unwrap_or_default().join(" ")
+    );
+
+    let result = guest_exec(config, command)?;
+
+    for line in result.stdout.lines() {
+        log!("GUEST OUT | {}", line);
+    }
+
+    for line in result.stderr.lines() {
+        log!("GUEST ERR | {}", line);
+    }
+
+    if let Some(signal) = result.signal {
+        return Err(CustomError::from_message(&format!(
+            "The guest command `{}` in vm `{}` was terminated by signal {}.",
+            command.path,
+            config.vm_name,
+            signal
+        )));
+    }
+
+    if result.exit_code != Some(0) {
+        return Err(CustomError::from_message(&format!(
+            "The guest command `{}` in vm `{}` failed with exit code {}.",
+            command.path,
+            config.vm_name,
+            result.exit_code.map(|x| x.to_string()).unwrap_or_else(|| "none".to_string())
+        )));
+    }
+
+    Ok(())
+}
+
+/// Runs the configured guest commands in order and stops at the first failure.
+pub fn run_guest_hooks(config: &VmConfig, hook_name: &str, commands: &Option<Vec<GuestCommandConfig>>) -> Result {
+
+    let commands = match commands {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    for command in commands {
+        run_guest_command(config, hook_name, command)?;
+    }
+
+    Ok(())
+}
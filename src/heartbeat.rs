@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use crate::global::prelude::*;
+
+static HEARTBEAT_TIMEOUT_SECONDS: u64 = 10;
+
+pub enum HeartbeatSignal {
+    Start,
+    Success,
+    Failure(String),
+    /// The run was skipped or deferred on purpose, e.g. by a blackout window.
+    Skipped(String),
+}
+
+fn send_ping(url: &str, body: &str) -> Result {
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(HEARTBEAT_TIMEOUT_SECONDS))
+        .build()?;
+
+    let response = client.post(url)
+        .body(body.to_string())
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(CustomError::from_message(&format!(
+            "The heartbeat url `{}` responded with status {}.",
+            url,
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Pings the `heartbeat_url` of the vm in the healthchecks.io style:
+/// `<url>/start` when a run starts, `<url>` when it succeeds and `<url>/fail` when it fails.
+/// A run that is skipped on purpose posts the reason to `<url>/log`, which is recorded without changing the status of the check,
+/// so a blackout window that covers every scheduled run still lets the check go down.
+/// A failed ping is logged but does not fail the run.
+pub fn ping_heartbeat(config: &VmConfig, signal: HeartbeatSignal) -> Result {
+
+    let base_url = match &config.heartbeat_url {
+        Some(x) => x.trim_end_matches('/'),
+        None => return Ok(()),
+    };
+
+    let (url, body) = match signal {
+        HeartbeatSignal::Start => (format!("{}/start", base_url), String::new()),
+        HeartbeatSignal::Success => (base_url.to_string(), String::new()),
+        HeartbeatSignal::Failure(message) => (format!("{}/fail", base_url), message),
+        HeartbeatSignal::Skipped(reason) => (format!("{}/log", base_url), format!("Skipped: {}", reason)),
+    };
+
+    if let Err(err) = send_ping(&url, &body) {
+        log!("Heartbeat ping for vm `{}` failed: {}", config.vm_name, err.kind.to_string());
+    }
+
+    Ok(())
+}
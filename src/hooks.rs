@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::global::prelude::*;
+use crate::global::app_config::HookConfig;
+use crate::global::bash_shell::{exec_with_options, ExecOptions};
+
+static DEFAULT_HOOK_TIMEOUT_SECONDS: u64 = 600;
+
+/// Runs a host-side hook command if it is configured.
+/// The output is logged, so it ends up in the report. A non zero exit code or a timeout is an error.
+pub fn run_hook(config: &VmConfig, hook_name: &str, hook: &Option<HookConfig>, snapshot_name: &str) -> Result {
+
+    let hook = match hook {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let timeout_seconds = hook.timeout_seconds.unwrap_or(DEFAULT_HOOK_TIMEOUT_SECONDS);
+
+    log!("Running `{}` hook for vm `{}` ...", hook_name, config.vm_name);
+
+    let ps = exec_with_options(&hook.command, &ExecOptions {
+        log_output: true,
+        timeout: Some(Duration::from_secs(timeout_seconds)),
+        env: vec![
+            ("XDXD_HOOK".to_string(), hook_name.to_string()),
+            ("XDXD_VM_NAME".to_string(), config.vm_name.clone()),
+            ("XDXD_SNAPSHOT_NAME".to_string(), snapshot_name.to_string()),
+            ("XDXD_HOSTNAME".to_string(), app_config().hostname.clone()),
+        ],
+    })?;
+
+    if ps.timed_out {
+        return Err(CustomError::from_message(&format!(
+            "The `{}` hook for vm `{}` timed out after {} second(s).",
+            hook_name,
+            config.vm_name,
+            timeout_seconds
+        )));
+    }
+
+    if !ps.success {
+        return Err(CustomError::from_message(&format!(
+            "The `{}` hook for vm `{}` failed with exit code {}.",
+            hook_name,
+            config.vm_name,
+            ps.status_code.map(|x| x.to_string()).unwrap_or_else(|| "none".to_string())
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::io::Write;
+use std::path::Path;
+
+use clap::Arg;
+use chrono::{Local, NaiveDateTime};
+
+use crate::global::prelude::*;
+use crate::global::bash_shell::{exec_without_log, quote};
+use crate::cron_schedule::CronSchedule;
+
+/// How many upcoming runs of the daemon and the systemd timer are compared.
+static VERIFY_ITERATIONS: usize = 20;
+
+struct InstallUnitsCommandOptions {
+    output_directory: Option<String>,
+}
+
+fn install_units_command_options() -> Result<InstallUnitsCommandOptions> {
+
+    const OUTPUT_DIRECTORY_VALUE: &str = "output-directory";
+
+    let matches = cli().command_config(|x| {
+
+        x.arg(Arg::with_name(OUTPUT_DIRECTORY_VALUE)
+            .short("o")
+            .long(OUTPUT_DIRECTORY_VALUE)
+            .value_name(OUTPUT_DIRECTORY_VALUE)
+            .help("The directory to write the unit files to (e.g. /etc/systemd/system). Prints them to stdout if omitted.")
+            .required(false)
+            .takes_value(true)
+        )
+    });
+
+    Ok(InstallUnitsCommandOptions {
+        output_directory: matches.value_of(OUTPUT_DIRECTORY_VALUE).map(|x| x.to_string()),
+    })
+}
+
+struct UnitFile {
+    file_name: String,
+    content: String,
+}
+
+fn unit_name(config: &VmConfig) -> String {
+
+    format!("xdxd-snapshot-rotator-{}", config.vm_name)
+}
+
+fn service_unit(config: &VmConfig) -> Result<UnitFile> {
+
+    let executable_path = ::std::env::current_exe()?.get_as_string()?;
+    let config_directory = ::std::fs::canonicalize(config_directory())?.get_as_string()?;
+
+    let content = format!(
+        "[Unit]\n\
+         Description=xdxd-snapshot-rotator snapshot for vm {vm_name}\n\
+         Wants=libvirtd.service\n\
+         After=libvirtd.service\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         Environment=CONFIG_DIRECTORY={config_directory}\n\
+         ExecStart={executable_path} create --vm-name {vm_name}\n",
+        vm_name = config.vm_name,
+        config_directory = config_directory,
+        executable_path = executable_path,
+    );
+
+    Ok(UnitFile {
+        file_name: format!("{}.service", unit_name(config)),
+        content,
+    })
+}
+
+fn timer_unit(config: &VmConfig, schedule: &CronSchedule) -> UnitFile {
+
+    let on_calendar = schedule.to_systemd_calendar()
+        .into_iter()
+        .map(|x| format!("OnCalendar={}\n", x))
+        .collect_vec()
+        .join("");
+
+    let content = format!(
+        "[Unit]\n\
+         Description=xdxd-snapshot-rotator schedule for vm {vm_name} ({expression})\n\
+         \n\
+         [Timer]\n\
+         {on_calendar}\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        vm_name = config.vm_name,
+        expression = schedule.expression,
+        on_calendar = on_calendar,
+    );
+
+    UnitFile {
+        file_name: format!("{}.timer", unit_name(config)),
+        content,
+    }
+}
+
+/// Compares the next runs of the schedule with the ones `systemd-analyze calendar` computes for the
+/// generated `OnCalendar=` values, so that the timer never fires at other times than the daemon would.
+/// When both day fields are restricted, `cron` fires when either one matches while a single `OnCalendar=` value
+/// requires both, so the timer gets one `OnCalendar=` value per day field instead.
+/// Skipped when `systemd-analyze` is not available.
+fn verify_systemd_calendar(config: &VmConfig, schedule: &CronSchedule) -> Result {
+
+    if !exec_without_log("command -v systemd-analyze")?.success {
+        elog!("`systemd-analyze` is not available. The timer for vm `{}` is not verified.", config.vm_name);
+        return Ok(());
+    }
+
+    let calendar = schedule.to_systemd_calendar();
+
+    let ps = exec_without_log(&format!(
+        "systemd-analyze calendar --iterations={} {}",
+        VERIFY_ITERATIONS,
+        calendar.iter().map(|x| quote(x)).collect_vec().join(" ")
+    ))?.as_result()?;
+
+    // `Next elapse: Mon 2026-10-19 02:00:00 UTC` and `Iter. #2: Tue 2026-10-20 02:00:00 UTC`, in local time.
+    let mut systemd_runs = ps.stdout.lines()
+        .map(|x| x.trim())
+        .filter(|x| x.starts_with("Next elapse:") || x.starts_with("Iter. #"))
+        .filter_map(|x| {
+            let parts = x.splitn(2, ':').nth(1)?.split_whitespace().collect_vec();
+            NaiveDateTime::parse_from_str(&format!("{} {}", parts.get(1)?, parts.get(2)?), "%Y-%m-%d %H:%M:%S").ok()
+        })
+        .collect_vec();
+
+    systemd_runs.sort();
+    systemd_runs.dedup();
+    systemd_runs.truncate(VERIFY_ITERATIONS);
+
+    let mut daemon_runs = Vec::new();
+    let mut current = Local::now();
+
+    while daemon_runs.len() < VERIFY_ITERATIONS {
+        match schedule.next_after(&current) {
+            Some(next) => {
+                daemon_runs.push(next.naive_local());
+                current = next;
+            },
+            None => break,
+        }
+    }
+
+    if systemd_runs != daemon_runs {
+
+        let format_runs = |runs: &[NaiveDateTime]| runs.iter()
+            .take(5)
+            .map(|x| x.format("%Y-%m-%d %H:%M").to_string())
+            .collect_vec()
+            .join(", ");
+
+        return Err(CustomError::user_error(&format!(
+            "The systemd timer for vm `{}` would not fire at the same times as `{}`. Daemon: {} ... systemd ({}): {} ...",
+            config.vm_name,
+            schedule.expression,
+            format_runs(&daemon_runs),
+            calendar.join(" | "),
+            format_runs(&systemd_runs)
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn install_units_command() -> Result {
+
+    let options = install_units_command_options()?;
+
+    let configs = app_config().snapshot_config.as_ref()
+        .map(|x| x.values().cloned().order_by(|x| x.vm_name.clone()).collect_vec())
+        .unwrap_or_default();
+
+    let mut unit_files = Vec::new();
+
+    for config in configs {
+
+        let expression = match &config.schedule {
+            Some(x) => x.clone(),
+            None => {
+                elog!("Vm `{}` has no `schedule` configured. Skipping.", config.vm_name);
+                continue;
+            }
+        };
+
+        let schedule = CronSchedule::parse(&expression)
+            .replace_error(|| CustomError::user_error(&format!(
+                "Invalid `schedule` for vm `{}`: `{}`.",
+                config.vm_name,
+                expression
+            )))?;
+
+        verify_systemd_calendar(&config, &schedule)?;
+
+        unit_files.push(service_unit(&config)?);
+        unit_files.push(timer_unit(&config, &schedule));
+    }
+
+    match options.output_directory {
+        Some(output_directory) => {
+
+            let directory = Path::new(&output_directory).create_directory()?;
+
+            for unit_file in unit_files {
+
+                let file_path = directory.join(&unit_file.file_name);
+
+                ::std::fs::write(&file_path, unit_file.content)?;
+
+                log!("Written `{}`.", file_path.get_as_string()?);
+            }
+        },
+        None => {
+
+            let stdout = &mut ::std::io::stdout();
+
+            for unit_file in unit_files {
+                write!(stdout, "# {}\n{}\n", unit_file.file_name, unit_file.content)?;
+            }
+        }
+    }
+
+    Ok(())
+}
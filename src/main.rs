@@ -11,6 +11,19 @@ mod create_snapshot;
 mod list_snapshot;
 mod snapshot_helper;
 mod clear_cache;
+mod blackout;
+mod hooks;
+mod guest_agent;
+mod guard;
+mod heartbeat;
+mod run_history;
+mod metrics;
+mod snapshot_report;
+mod digest;
+mod cron_schedule;
+mod daemon;
+mod status_server;
+mod install_units;
 
 use crate::global::prelude::*;
 use crate::global::errors::CustomErrorKind;
@@ -19,6 +32,10 @@ use crate::config::config_command;
 use crate::create_snapshot::{create_shapshot_command};
 use crate::list_snapshot::list_shapshot_command;
 use crate::clear_cache::clear_cache_command;
+use crate::daemon::daemon_command;
+use crate::install_units::install_units_command;
+use crate::guard::guard_command;
+use crate::digest::digest_command;
 
 fn main() {
 
@@ -33,6 +50,10 @@ fn main_result() -> Result {
     cli().register_command("list", Box::new(list_shapshot_command))?;
     cli().register_command("create", Box::new(create_shapshot_command))?;
     cli().register_command("config", Box::new(config_command))?;
+    cli().register_command("daemon", Box::new(daemon_command))?;
+    cli().register_command("install-units", Box::new(install_units_command))?;
+    cli().register_command("guard", Box::new(guard_command))?;
+    cli().register_command("digest", Box::new(digest_command))?;
 
     match cli().run() {
         Err(err) => {
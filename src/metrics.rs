@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::global::prelude::*;
+use crate::global::state_file::{read_state, update_state};
+use crate::run_history::RunRecord;
+use crate::snapshot_helper::RemainingSnapshot;
+
+static METRICS_STATE_FILE_NAME: &str = "metrics-state.json";
+static DEFAULT_TEXTFILE_PATH: &str = "/var/lib/node_exporter/textfile_collector/xdxd_snapshot_rotator.prom";
+static METRIC_PREFIX: &str = "xdxd_snapshot_rotator";
+
+/// What the last runs of a vm left behind.
+/// Kept apart from the run history, so it survives the retention.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct VmMetrics {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub snapshot_count: Option<usize>,
+    pub oldest_snapshot: Option<DateTime<Utc>>,
+    pub newest_snapshot: Option<DateTime<Utc>>,
+    pub last_run_duration_seconds: Option<i64>,
+    pub last_run_deleted_snapshots: Option<usize>,
+    #[serde(default)]
+    pub last_run: Option<RunRecord>,
+    /// The snapshots after the last successful run, oldest first.
+    #[serde(default)]
+    pub snapshots: Option<Vec<RemainingSnapshot>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct MetricsState {
+    vms: HashMap<String, VmMetrics>,
+}
+
+/// Records the run in the metrics state and, with `metrics_config`, rewrites the node_exporter textfile
+/// with the gauges of every configured vm. A failure is logged but does not fail the run.
+pub fn update_metrics(record: &RunRecord, remaining_snapshots: Option<&[RemainingSnapshot]>) -> Result {
+
+    let textfile_path = app_config().metrics_config.as_ref()
+        .map(|x| x.textfile_path.clone().unwrap_or_else(|| DEFAULT_TEXTFILE_PATH.to_string()));
+
+    if let Err(err) = write_metrics(textfile_path.as_ref().map(|x| x.as_str()), record, remaining_snapshots) {
+        log!("The metrics could not be updated: {}", err.kind.to_string());
+    }
+
+    Ok(())
+}
+
+fn write_metrics(textfile_path: Option<&str>, record: &RunRecord, remaining_snapshots: Option<&[RemainingSnapshot]>) -> Result {
+
+    // The textfile is written under the lock as well, so an older state never overwrites a newer one.
+    update_state(METRICS_STATE_FILE_NAME, |state: &mut MetricsState| {
+
+        let metrics = state.vms.entry(record.vm_name.clone()).or_insert_with(VmMetrics::default);
+
+        if record.success {
+            metrics.last_success = Some(record.finished_at);
+        } else {
+            metrics.last_failure = Some(record.finished_at);
+        }
+
+        if let Some(remaining_snapshots) = remaining_snapshots {
+            metrics.snapshot_count = Some(remaining_snapshots.len());
+            metrics.oldest_snapshot = remaining_snapshots.iter().map(|x| x.date).min();
+            metrics.newest_snapshot = remaining_snapshots.iter().map(|x| x.date).max();
+            metrics.snapshots = Some(remaining_snapshots.to_vec());
+        }
+
+        metrics.last_run_duration_seconds = Some((record.finished_at - record.started_at).num_seconds());
+        metrics.last_run_deleted_snapshots = Some(record.deleted_snapshots.len());
+        metrics.last_run = Some(record.clone());
+
+        // Vms that were removed from the config should not linger as stale series.
+        if let Some(snapshot_config) = &app_config().snapshot_config {
+            state.vms.retain(|vm_name, _| snapshot_config.contains_key(vm_name));
+        }
+
+        if let Some(textfile_path) = textfile_path {
+            write_textfile(textfile_path, &render_metrics(state))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The recorded metrics of every vm by vm name, as of their last runs.
+pub fn recorded_metrics() -> Result<HashMap<String, VmMetrics>> {
+
+    let state: MetricsState = read_state(METRICS_STATE_FILE_NAME)?;
+
+    Ok(state.vms)
+}
+
+/// The gauges of every vm as of their last runs. Does not depend on the textfile being enabled.
+pub fn current_metrics() -> Result<String> {
+
+    let mut state: MetricsState = read_state(METRICS_STATE_FILE_NAME)?;
+
+    if let Some(snapshot_config) = &app_config().snapshot_config {
+        state.vms.retain(|vm_name, _| snapshot_config.contains_key(vm_name));
+    }
+
+    Ok(render_metrics(&state))
+}
+
+/// Writes to a temporary file next to the target and renames it,
+/// so node_exporter never reads a partially written file. It only reads files that end with `.prom`.
+fn write_textfile(textfile_path: &str, content: &str) -> Result {
+
+    let temp_file_path = format!("{}.tmp", textfile_path);
+
+    if let Some(directory) = Path::new(textfile_path).parent() {
+        directory.create_directory()?;
+    }
+
+    ::std::fs::write(&temp_file_path, content)?;
+    ::std::fs::rename(&temp_file_path, textfile_path)?;
+
+    Ok(())
+}
+
+fn render_metrics(state: &MetricsState) -> String {
+
+    let vms = state.vms.iter()
+        .order_by(|(vm_name, _)| vm_name.to_string())
+        .collect_vec();
+
+    let gauges: Vec<(&str, &str, Box<dyn Fn(&VmMetrics) -> Option<i64>>)> = vec![
+        (
+            "last_success_timestamp_seconds",
+            "Unix time of the last successful snapshot run.",
+            Box::new(|x| x.last_success.map(|y| y.timestamp())),
+        ),
+        (
+            "last_failure_timestamp_seconds",
+            "Unix time of the last failed snapshot run.",
+            Box::new(|x| x.last_failure.map(|y| y.timestamp())),
+        ),
+        (
+            "snapshot_count",
+            "The number of snapshots of the vm.",
+            Box::new(|x| x.snapshot_count.map(|y| y as i64)),
+        ),
+        (
+            "oldest_snapshot_timestamp_seconds",
+            "Unix time of the oldest snapshot. Alert on `time() - x`, it keeps growing when the runs stop.",
+            Box::new(|x| x.oldest_snapshot.map(|y| y.timestamp())),
+        ),
+        (
+            "newest_snapshot_timestamp_seconds",
+            "Unix time of the newest snapshot. Alert on `time() - x`, it keeps growing when the runs stop.",
+            Box::new(|x| x.newest_snapshot.map(|y| y.timestamp())),
+        ),
+        (
+            "last_run_duration_seconds",
+            "The duration of the last snapshot run.",
+            Box::new(|x| x.last_run_duration_seconds),
+        ),
+        (
+            "last_run_deleted_snapshots",
+            "The number of snapshots deleted by the last snapshot run.",
+            Box::new(|x| x.last_run_deleted_snapshots.map(|y| y as i64)),
+        ),
+    ];
+
+    let mut content = String::new();
+
+    for (name, help, value) in &gauges {
+
+        content.push_str(&format!("# HELP {}_{} {}\n", METRIC_PREFIX, name, help));
+        content.push_str(&format!("# TYPE {}_{} gauge\n", METRIC_PREFIX, name));
+
+        for (vm_name, metrics) in &vms {
+            if let Some(value) = value(metrics) {
+                content.push_str(&format!(
+                    "{}_{}{{vm=\"{}\"}} {}\n",
+                    METRIC_PREFIX,
+                    name,
+                    escape_label_value(vm_name),
+                    value
+                ));
+            }
+        }
+    }
+
+    content
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::global::prelude::*;
+use crate::global::state_file::{read_state, update_state};
+
+static RUN_HISTORY_STATE_FILE_NAME: &str = "run-history.json";
+static FAILURE_COUNTERS_STATE_FILE_NAME: &str = "failure-counters.json";
+
+/// Runs older than this are dropped from the history.
+static RUN_HISTORY_RETENTION_DAYS: i64 = 35;
+
+/// A single snapshot run of a vm.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunRecord {
+    pub vm_name: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub snapshot_name: Option<String>,
+    pub deleted_snapshots: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct RunHistory {
+    runs: Vec<RunRecord>,
+}
+
+/// The number of consecutive failed runs by vm name. Kept apart from the history, so it survives the retention.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FailureCounters {
+    consecutive_failures: HashMap<String, u32>,
+}
+
+/// Appends the run to the history and updates the failure counter of the vm.
+/// Both files are updated under their locks, because runs for different vms finish at the same time in batch mode.
+/// The history lock is always taken first.
+pub fn record_run(record: RunRecord) -> Result {
+
+    let retention_start = Utc::now() - Duration::days(RUN_HISTORY_RETENTION_DAYS);
+
+    update_state(RUN_HISTORY_STATE_FILE_NAME, |history: &mut RunHistory| {
+
+        update_state(FAILURE_COUNTERS_STATE_FILE_NAME, |counters: &mut FailureCounters| {
+
+            if record.success {
+                counters.consecutive_failures.remove(&record.vm_name);
+            } else {
+                *counters.consecutive_failures.entry(record.vm_name.clone()).or_insert(0) += 1;
+            }
+
+            Ok(())
+        })?;
+
+        history.runs.retain(|x| x.finished_at >= retention_start);
+        history.runs.push(record);
+
+        Ok(())
+    })
+}
+
+/// The number of runs of the vm that failed since its last successful one.
+pub fn consecutive_failures(vm_name: &str) -> Result<u32> {
+
+    let counters: FailureCounters = read_state(FAILURE_COUNTERS_STATE_FILE_NAME)?;
+
+    Ok(counters.consecutive_failures.get(vm_name).cloned().unwrap_or(0))
+}
+
+/// Returns the recorded runs that finished after `since`, oldest first.
+pub fn runs_since(since: DateTime<Utc>) -> Result<Vec<RunRecord>> {
+
+    let history: RunHistory = read_state(RUN_HISTORY_STATE_FILE_NAME)?;
+
+    let runs = history.runs.into_iter()
+        .filter(|x| x.finished_at >= since)
+        .order_by(|x| x.finished_at)
+        .collect_vec();
+
+    Ok(runs)
+}
@@ -1,6 +1,18 @@
+use std::fs::File;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
 use crate::global::prelude::*;
+use crate::global::file_lock::{lock_file, wait_for_lock, LOCK_DIRECTORY_NAME};
+use crate::global::state_file::{read_state, update_state};
+use crate::hooks::run_hook;
 use chrono::{DateTime, Utc, TimeZone};
 
+static PINNED_SNAPSHOTS_STATE_FILE_NAME: &str = "pinned-snapshots.json";
+
 #[derive(Debug)]
 pub struct VmSnapshot {
     pub vm_name: String,
@@ -43,8 +55,76 @@ pub fn list_snapshots(config: &VmConfig) -> Result<Vec<VmSnapshot>> {
     Ok(snapshots)
 }
 
-pub fn clear_cache(config: &VmConfig) -> Result {
-    let snapshots = list_snapshots(config)?;
+/// Snapshots that are excluded from rotation until the given time.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PinnedSnapshots {
+    pinned_until: HashMap<String, DateTime<Utc>>,
+}
+
+impl PinnedSnapshots {
+
+    fn get(&self, snapshot_name: &str) -> Option<DateTime<Utc>> {
+        self.pinned_until.get(snapshot_name)
+            .cloned()
+            .filter(|x| *x > Utc::now())
+    }
+}
+
+/// Excludes the snapshot from rotation until the given time. Expired pins are removed.
+pub fn pin_snapshot(snapshot_name: &str, pinned_until: DateTime<Utc>) -> Result {
+
+    update_state(PINNED_SNAPSHOTS_STATE_FILE_NAME, |state: &mut PinnedSnapshots| {
+
+        let now = Utc::now();
+
+        state.pinned_until.retain(|_, x| *x > now);
+        state.pinned_until.insert(snapshot_name.to_string(), pinned_until);
+
+        Ok(())
+    })
+}
+
+/// A snapshot that is left after the rotation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemainingSnapshot {
+    pub snapshot_name: String,
+    pub date: DateTime<Utc>,
+    pub pinned_until: Option<DateTime<Utc>>,
+}
+
+/// Lists the snapshots of the vm with the time they are pinned until, oldest first.
+pub fn snapshot_inventory(config: &VmConfig) -> Result<Vec<RemainingSnapshot>> {
+
+    let pinned_snapshots: PinnedSnapshots = read_state(PINNED_SNAPSHOTS_STATE_FILE_NAME)?;
+
+    let snapshots = list_snapshots(config)?
+        .into_iter()
+        .order_by(|x| x.date)
+        .map(|x| RemainingSnapshot {
+            pinned_until: pinned_snapshots.get(&x.snapsnot_name),
+            snapshot_name: x.snapsnot_name,
+            date: x.date,
+        })
+        .collect_vec();
+
+    Ok(snapshots)
+}
+
+pub struct RotationResult {
+    pub deleted_snapshots: Vec<String>,
+    /// Oldest first.
+    pub remaining_snapshots: Vec<RemainingSnapshot>,
+}
+
+/// Deletes the oldest snapshots until `min_snapshot_count` are left.
+/// Pinned snapshots are never deleted and do not count towards `min_snapshot_count`.
+pub fn clear_cache(config: &VmConfig) -> Result<RotationResult> {
+    let pinned_snapshots: PinnedSnapshots = read_state(PINNED_SNAPSHOTS_STATE_FILE_NAME)?;
+
+    let snapshots = list_snapshots(config)?
+        .into_iter()
+        .filter(|x| pinned_snapshots.get(&x.snapsnot_name).is_none())
+        .collect_vec();
 
     let take_count = if ((snapshots.len() as i32) - config.min_snapshot_count) < 0 {
         0
@@ -58,13 +138,217 @@ pub fn clear_cache(config: &VmConfig) -> Result {
         .take(take_count as usize)
         .collect_vec();
 
+    let mut deleted_snapshots = Vec::new();
+
     for snapshot in for_delete {
+        run_hook(config, "pre_delete", &config.pre_delete, &snapshot.snapsnot_name)?;
+
         log!("Deleting snapshot `{}` ...", snapshot.snapsnot_name);
 
         bash_exec!("virsh snapshot-delete --domain {} --snapshotname {}", snapshot.vm_name, snapshot.snapsnot_name);
+
+        run_hook(config, "post_delete", &config.post_delete, &snapshot.snapsnot_name)?;
+
+        deleted_snapshots.push(snapshot.snapsnot_name);
     }
 
-    bash_exec_no_log!("virsh snapshot-list --domain {} --internal", config.vm_name);
+    let remaining_snapshots = snapshot_inventory(config)?;
+
+    log!("Remaining snapshots for vm `{}`: {}", config.vm_name, remaining_snapshots.len());
+
+    for snapshot in &remaining_snapshots {
+        match snapshot.pinned_until {
+            Some(pinned_until) => log!("    {} (pinned until {})", snapshot.snapshot_name, pinned_until.format("%Y-%m-%d %H:%M:%S UTC")),
+            None => log!("    {}", snapshot.snapshot_name),
+        }
+    }
+
+    Ok(RotationResult {
+        deleted_snapshots,
+        remaining_snapshots,
+    })
+}
+
+/// Reverts the vm to the given snapshot.
+pub fn revert_snapshot(config: &VmConfig, snapshot_name: &str) -> Result {
+
+    let _lock = lock_vm(config)?;
+
+    log!("Reverting vm `{}` to snapshot `{}` ...", config.vm_name, snapshot_name);
+
+    bash_exec!("virsh snapshot-revert --domain {} --snapshotname {}", config.vm_name, snapshot_name);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub struct VmDisk {
+    pub target: String,
+    pub source: String,
+}
+
+pub fn list_disks(config: &VmConfig) -> Result<Vec<VmDisk>> {
+    let ps = bash_exec_no_log!("virsh domblklist --domain {} --details", config.vm_name);
+
+    let disks = ps.stdout
+        .split('\n')
+        .skip(2)
+        .map(|line| line.split(' ').filter(|x| x != &"").collect_vec())
+        .filter(|parts| parts.len() >= 4 && parts[1] == "disk")
+        .map(|parts| VmDisk {
+            target: parts[2].to_string(),
+            source: parts[3..].join(" "),
+        })
+        .collect_vec();
+
+    Ok(disks)
+}
+
+/// Checks that a freshly created snapshot is present and covers the disks of the vm.
+/// Returns an error describing the first problem found.
+pub fn verify_snapshot(config: &VmConfig, snapshot_name: &str) -> Result {
+
+    log!("Verifying snapshot `{}` ...", snapshot_name);
+
+    let snapshots = list_snapshots(config)?;
+
+    if !snapshots.iter().any(|x| x.snapsnot_name == snapshot_name) {
+        return Err(CustomError::from_message(&format!(
+            "The snapshot `{}` is not present in the snapshot list of vm `{}`.",
+            snapshot_name,
+            config.vm_name
+        )));
+    }
+
+    let ps = bash_exec_no_log!("virsh snapshot-dumpxml --domain {} --snapshotname {}", config.vm_name, snapshot_name);
+
+    let document = roxmltree::Document::parse(&ps.stdout)?;
+
+    let snapshot_disks = document.root_element()
+        .children()
+        .filter(|x| x.has_tag_name("disks"))
+        .flat_map(|x| x.children())
+        .filter(|x| x.has_tag_name("disk"))
+        .filter(|x| x.attribute("snapshot") != Some("no"))
+        .filter_map(|x| x.attribute("name").map(|name| name.to_string()))
+        .collect_vec();
+
+    let disks = list_disks(config)?;
+
+    for disk in &disks {
+        if !snapshot_disks.contains(&disk.target) {
+            return Err(CustomError::from_message(&format!(
+                "The snapshot `{}` does not include disk `{}` of vm `{}`.",
+                snapshot_name,
+                disk.target,
+                config.vm_name
+            )));
+        }
+    }
+
+    if config.verify_with_qemu_img.unwrap_or(false) {
+        for disk in disks.iter().filter(|x| x.source != "-") {
+            let ps = crate::global::bash_shell::exec(&format!("qemu-img check -U {}", disk.source))?;
+
+            if !ps.success {
+                return Err(CustomError::from_message(&format!(
+                    "`qemu-img check` failed for disk `{}` ({}) of vm `{}`.",
+                    disk.target,
+                    disk.source,
+                    config.vm_name
+                )));
+            }
+        }
+    }
+
+    log!("Snapshot `{}` verified.", snapshot_name);
+
+    Ok(())
+}
+
+/// Takes the per-vm lock that every mutating command must hold.
+/// The lock is released when the returned file is dropped.
+pub fn lock_vm(config: &VmConfig) -> Result<File> {
+
+    let lock_directory = config_directory().join(LOCK_DIRECTORY_NAME).create_directory()?;
+    let lock_file_path = lock_directory.join(format!("{}.lock", config.vm_name)).get_as_string()?;
+
+    let read_lock_owner = || ::std::fs::read_to_string(&lock_file_path)
+        .map(|x| x.trim().to_string())
+        .unwrap_or_default();
+
+    let file = match lock_file(&lock_file_path)? {
+        Some(file) => Some(file),
+        None => {
+            log!("Vm `{}` is locked by process with PID {}. Waiting ...", config.vm_name, read_lock_owner());
+
+            let timeout = app_config().lock_timeout_seconds.map(Duration::from_secs);
+
+            wait_for_lock(&lock_file_path, timeout)?
+        }
+    };
+
+    let mut file = file.ok_or_else(|| CustomError::user_error(&format!(
+        "Vm `{}` is locked by process with PID {}. Gave up waiting after {} second(s).",
+        config.vm_name,
+        read_lock_owner(),
+        app_config().lock_timeout_seconds.unwrap_or(0)
+    )))?;
+
+    file.set_len(0)?;
+    write!(file, "{}", ::std::process::id())?;
+
+    Ok(file)
+}
+
+/// A slot of the host-wide snapshot concurrency limit.
+/// The slot is released when this is dropped.
+pub struct SnapshotSlot {
+    #[allow(unused)]
+    file: Option<File>,
+    pub wait_time: Duration,
+}
+
+/// Waits until one of the `max_concurrent_snapshots` host-wide slots is free.
+/// Each slot is a lock file, so the limit applies across independent invocations.
+pub fn acquire_snapshot_slot(config: &VmConfig) -> Result<SnapshotSlot> {
+
+    let start = Instant::now();
+
+    let max_concurrent_snapshots = match app_config().max_concurrent_snapshots {
+        Some(x) => x,
+        None => return Ok(SnapshotSlot { file: None, wait_time: start.elapsed() }),
+    };
+
+    let lock_directory = config_directory().join(LOCK_DIRECTORY_NAME).create_directory()?;
+
+    let slot_file_paths = (0..max_concurrent_snapshots)
+        .map(|x| lock_directory.join(format!("snapshot-slot-{}.lock", x)).get_as_string())
+        .collect::<Result<Vec<String>>>()?;
+
+    let mut is_waiting = false;
+
+    loop {
+
+        for slot_file_path in &slot_file_paths {
+
+            if let Some(file) = lock_file(slot_file_path)? {
+
+                let wait_time = start.elapsed();
+
+                if is_waiting {
+                    log!("Vm `{}` acquired a snapshot slot after waiting {} second(s).", config.vm_name, wait_time.as_secs());
+                }
+
+                return Ok(SnapshotSlot { file: Some(file), wait_time });
+            }
+        }
+
+        if !is_waiting {
+            log!("All {} snapshot slot(s) on this host are in use. Vm `{}` is waiting ...", slot_file_paths.len(), config.vm_name);
+            is_waiting = true;
+        }
+
+        ::std::thread::sleep(Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::global::prelude::*;
+use crate::create_snapshot::CreateSnapshotResult;
+use crate::digest::format_age;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReportedSnapshot {
+    pub snapshot_name: String,
+    pub date: DateTime<Utc>,
+    pub age: String,
+    pub pinned_until: Option<DateTime<Utc>>,
+    /// Set for the snapshot that was created by the run.
+    pub created: bool,
+}
+
+/// What a successful snapshot run did and which snapshots the vm has after it.
+#[derive(Serialize, Debug, Clone)]
+pub struct SnapshotReport {
+    pub host: String,
+    pub vm_name: String,
+    pub created_snapshot: String,
+    pub deleted_snapshots: Vec<String>,
+    /// Oldest first.
+    pub remaining_snapshots: Vec<ReportedSnapshot>,
+    pub duration_seconds: u64,
+    pub slot_wait_seconds: u64,
+    pub generated_at: DateTime<Utc>,
+}
+
+pub fn build_snapshot_report(config: &VmConfig, result: &CreateSnapshotResult) -> SnapshotReport {
+
+    let now = Utc::now();
+
+    let remaining_snapshots = result.remaining_snapshots.iter()
+        .map(|x| ReportedSnapshot {
+            snapshot_name: x.snapshot_name.clone(),
+            date: x.date,
+            age: format_age(now - x.date),
+            pinned_until: x.pinned_until,
+            created: x.snapshot_name == result.snapshot_name,
+        })
+        .collect_vec();
+
+    SnapshotReport {
+        host: app_config().hostname.clone(),
+        vm_name: config.vm_name.clone(),
+        created_snapshot: result.snapshot_name.clone(),
+        deleted_snapshots: result.deleted_snapshots.clone(),
+        remaining_snapshots,
+        duration_seconds: result.duration.as_secs(),
+        slot_wait_seconds: result.slot_wait.as_secs(),
+        generated_at: now,
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::global::prelude::*;
+use crate::global::error_handler::handle_error;
+use crate::run_history::consecutive_failures;
+use crate::metrics::{current_metrics, recorded_metrics};
+use crate::digest::format_age;
+
+static REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+/// Starts the daemon status server on a background thread:
+/// `/healthz`, `/metrics` in the Prometheus text format and `/status` with the last runs and the snapshots of every vm.
+pub fn start_status_server(address: &str) -> Result {
+
+    let listener = TcpListener::bind(address)
+        .replace_error(|| CustomError::user_error(&format!("The status server could not listen on `{}`.", address)))?;
+
+    log!("Status server listening on http://{}.", address);
+
+    let started_at = Utc::now();
+
+    ::std::thread::spawn(move || serve(listener, started_at).or_else(|err| handle_error(&err)));
+
+    Ok(())
+}
+
+/// Handles every connection on its own thread, so a slow client does not hold up the others.
+/// A failed request is logged and does not stop the server.
+fn serve(listener: TcpListener, started_at: DateTime<Utc>) -> Result {
+
+    for stream in listener.incoming() {
+
+        let stream = match stream {
+            Ok(x) => x,
+            Err(err) => {
+                log!("A status server connection failed: {}", err);
+                continue;
+            },
+        };
+
+        ::std::thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, started_at) {
+                let _ = logger().log(&format!("A status server request failed: {}", err.kind.to_string()));
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, started_at: DateTime<Utc>) -> Result {
+
+    stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SECONDS)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SECONDS)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+
+    reader.read_line(&mut request_line)?;
+
+    // The headers are not used, but they have to be read before the response is written.
+    loop {
+        let mut header = String::new();
+
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let parts = request_line.split_whitespace().collect_vec();
+
+    let (method, path) = match (parts.get(0), parts.get(1)) {
+        (Some(method), Some(path)) => (*method, path.split('?').next().unwrap_or(path)),
+        _ => return write_response(&mut stream, "400 Bad Request", "text/plain", "Bad request.\n"),
+    };
+
+    if method != "GET" {
+        return write_response(&mut stream, "405 Method Not Allowed", "text/plain", "Only GET is supported.\n");
+    }
+
+    let response = match path {
+        "/healthz" => Ok(("text/plain", "ok\n".to_string())),
+        "/metrics" => current_metrics().map(|x| ("text/plain; version=0.0.4", x)),
+        "/status" => status_json(started_at).and_then(|x| Ok(("application/json", serde_json::to_string_pretty(&x)?))),
+        _ => return write_response(&mut stream, "404 Not Found", "text/plain", "Not found.\n"),
+    };
+
+    match response {
+        Ok((content_type, body)) => write_response(&mut stream, "200 OK", content_type, &body),
+        Err(err) => {
+            log!("The status server could not build the response for `{}`: {}", path, err.kind.to_string());
+            write_response(&mut stream, "500 Internal Server Error", "text/plain", &format!("{}\n", err.kind.to_string()))
+        },
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result {
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// The last run and the snapshots of every configured vm, as recorded by their last runs.
+/// Nothing here calls virsh, so a request is never held up by libvirt.
+fn status_json(started_at: DateTime<Utc>) -> Result<Value> {
+
+    let now = Utc::now();
+
+    let recorded = recorded_metrics()?;
+
+    let configs = app_config().snapshot_config.as_ref()
+        .map(|x| x.values().cloned().order_by(|x| x.vm_name.clone()).collect_vec())
+        .unwrap_or_default();
+
+    let mut vms = Vec::new();
+
+    for config in configs {
+
+        let metrics = recorded.get(&config.vm_name).cloned().unwrap_or_default();
+
+        let snapshots = metrics.snapshots.map(|x| x.iter()
+            .map(|y| json!({
+                "snapshot_name": y.snapshot_name,
+                "date": y.date,
+                "age": format_age(now - y.date),
+                "pinned_until": y.pinned_until,
+            }))
+            .collect_vec());
+
+        vms.push(json!({
+            "vm_name": config.vm_name,
+            "schedule": config.schedule,
+            "last_run": metrics.last_run,
+            "last_success_at": metrics.last_success,
+            "last_failure_at": metrics.last_failure,
+            "consecutive_failures": consecutive_failures(&config.vm_name)?,
+            "snapshots": snapshots,
+        }));
+    }
+
+    Ok(json!({
+        "host": app_config().hostname,
+        "started_at": started_at,
+        "generated_at": now,
+        "vms": vms,
+    }))
+}